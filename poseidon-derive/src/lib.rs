@@ -0,0 +1,199 @@
+//! `#[derive(PoseidonHash)]`, implementing `poseidon_rs::hashable::ToFieldElements`
+//! so that values are hashed with `PoseidonHashable::poseidon_hash` (`hash_object`).
+//!
+//! Canonical layout of the encoding:
+//! - the domain tag of `#[poseidon(domain = "...")]` on the type, if any, packed with
+//!   `poseidon_rs::sponge::domain_tag` (its length is checked at expansion);
+//! - for enums, the index of the variant in declaration order;
+//! - the fields in declaration order, each with its own `ToFieldElements` encoding, except
+//!   `#[poseidon(skip)]` fields, which are left out, and `#[poseidon(pack_bytes = N)]` fields
+//!   (any `AsRef<[u8]>`), encoded as their byte length followed by the bytes composed
+//!   `N` per element, little-endian, as `compose_fields` does. A field with
+//!   `#[poseidon(domain = "...")]` is preceded by that domain tag.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::meta::ParseNestedMeta;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, LitByteStr,
+    LitInt, LitStr,
+};
+
+#[proc_macro_derive(PoseidonHash, attributes(poseidon))]
+pub fn derive_poseidon_hash(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+enum FieldEncoding {
+    Default,
+    Skip,
+    PackBytes(usize),
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let domain = container_domain(&input.attrs)?;
+    let encode_domain = domain.map(|domain| encode_domain_tag(&domain));
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, encode) = encode_fields(&data.fields)?;
+            quote! {
+                let Self #pattern = self;
+                #encode
+            }
+        }
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            for (index, variant) in data.variants.iter().enumerate() {
+                let index = index as u64;
+                let name = &variant.ident;
+                let (pattern, encode) = encode_fields(&variant.fields)?;
+                arms.push(quote! {
+                    Self::#name #pattern => {
+                        out.push(::poseidon_rs::Fr::from(#index));
+                        #encode
+                    }
+                });
+            }
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(data) => {
+            return Err(Error::new_spanned(
+                data.union_token,
+                "PoseidonHash cannot be derived for unions",
+            ))
+        }
+    };
+
+    for param in input.generics.type_params_mut() {
+        param
+            .bounds
+            .push(parse_quote!(::poseidon_rs::hashable::ToFieldElements));
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::poseidon_rs::hashable::ToFieldElements for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn append_fields(&self, out: &mut ::std::vec::Vec<::poseidon_rs::Fr>) {
+                #encode_domain
+                #body
+            }
+        }
+    })
+}
+
+/// Destructuring pattern binding every field, and the code encoding them.
+fn encode_fields(fields: &Fields) -> syn::Result<(TokenStream2, TokenStream2)> {
+    let mut bindings = Vec::new();
+    let mut encode = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let binding = format_ident!("__field_{}", i);
+        let (domain, encoding) = field_encoding(&field.attrs)?;
+        encode.extend(domain.map(|domain| encode_domain_tag(&domain)));
+        encode.push(match encoding {
+            FieldEncoding::Default => quote! {
+                ::poseidon_rs::hashable::ToFieldElements::append_fields(#binding, out);
+            },
+            FieldEncoding::Skip => quote! {},
+            FieldEncoding::PackBytes(width) => quote! {
+                ::poseidon_rs::hashable::append_packed_bytes(
+                    ::std::convert::AsRef::<[u8]>::as_ref(#binding),
+                    #width,
+                    out,
+                );
+            },
+        });
+        bindings.push(match &field.ident {
+            Some(ident) => quote! { #ident: #binding },
+            None => quote! { #binding },
+        });
+    }
+    let pattern = match fields {
+        Fields::Named(_) => quote! { { #(#bindings),* } },
+        Fields::Unnamed(_) => quote! { ( #(#bindings),* ) },
+        Fields::Unit => quote! {},
+    };
+    Ok((pattern, quote! { #(#encode)* }))
+}
+
+/// Pushes the domain tag, whose length was checked by `parse_domain`.
+fn encode_domain_tag(domain: &LitByteStr) -> TokenStream2 {
+    quote! {
+        out.push(::poseidon_rs::sponge::domain_tag(#domain).unwrap());
+    }
+}
+
+/// Value of a `domain = "..."` attribute, which must be set only once.
+fn parse_domain(meta: &ParseNestedMeta, domain: &Option<LitByteStr>) -> syn::Result<LitByteStr> {
+    if domain.is_some() {
+        return Err(meta.error("duplicate `domain` attribute"));
+    }
+    let value: LitStr = meta.value()?.parse()?;
+    if value.value().len() > 31 {
+        return Err(meta.error("domain tag must be at most 31 bytes"));
+    }
+    Ok(LitByteStr::new(value.value().as_bytes(), value.span()))
+}
+
+fn container_domain(attrs: &[Attribute]) -> syn::Result<Option<LitByteStr>> {
+    let mut domain = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("poseidon")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("domain") {
+                domain = Some(parse_domain(&meta, &domain)?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported poseidon attribute, expected `domain`"))
+            }
+        })?;
+    }
+    Ok(domain)
+}
+
+fn field_encoding(attrs: &[Attribute]) -> syn::Result<(Option<LitByteStr>, FieldEncoding)> {
+    let mut domain = None;
+    let mut encoding = FieldEncoding::Default;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("poseidon")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("domain") {
+                domain = Some(parse_domain(&meta, &domain)?);
+                return Ok(());
+            }
+            if !matches!(encoding, FieldEncoding::Default) {
+                return Err(meta.error("conflicting poseidon field encodings"));
+            }
+            if meta.path.is_ident("skip") {
+                encoding = FieldEncoding::Skip;
+                Ok(())
+            } else if meta.path.is_ident("pack_bytes") {
+                let width: LitInt = meta.value()?.parse()?;
+                let width: usize = width.base10_parse()?;
+                if !(1..=31).contains(&width) {
+                    return Err(meta.error("pack_bytes must be between 1 and 31"));
+                }
+                encoding = FieldEncoding::PackBytes(width);
+                Ok(())
+            } else {
+                Err(meta.error(
+                    "unsupported poseidon attribute, expected `skip`, `pack_bytes` or `domain`",
+                ))
+            }
+        })?;
+    }
+    if let (Some(domain), FieldEncoding::Skip) = (&domain, &encoding) {
+        return Err(Error::new(
+            domain.span(),
+            "a skipped field cannot have a domain tag",
+        ));
+    }
+    Ok((domain, encoding))
+}
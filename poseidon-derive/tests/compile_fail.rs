@@ -0,0 +1,5 @@
+#[test]
+fn test_invalid_attributes() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
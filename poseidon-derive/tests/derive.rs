@@ -0,0 +1,94 @@
+use poseidon_derive::PoseidonHash;
+use poseidon_rs::hashable::{append_packed_bytes, PoseidonHashable, ToFieldElements};
+use poseidon_rs::sponge::domain_tag;
+use poseidon_rs::Fr;
+
+#[derive(PoseidonHash)]
+#[poseidon(domain = "transfer-request")]
+struct Transfer {
+    amount: u64,
+    recipient: [u8; 20],
+    #[poseidon(pack_bytes = 16)]
+    memo: String,
+    #[poseidon(skip)]
+    #[allow(dead_code)]
+    cached: Option<Fr>,
+}
+
+#[derive(PoseidonHash)]
+struct Pair<T>(T, T);
+
+#[derive(PoseidonHash)]
+struct Ballot {
+    #[poseidon(domain = "voter")]
+    voter: u64,
+    #[poseidon(domain = "choice", pack_bytes = 31)]
+    choice: Vec<u8>,
+}
+
+#[derive(PoseidonHash)]
+enum Action {
+    Noop,
+    Vote { proposal: u32, approve: bool },
+    Transfer(Transfer),
+}
+
+fn transfer() -> Transfer {
+    Transfer {
+        amount: 100,
+        recipient: [0xaa; 20],
+        memo: "thanks".to_string(),
+        cached: None,
+    }
+}
+
+#[test]
+fn test_struct_layout() {
+    let request = transfer();
+    let mut expected = vec![domain_tag(b"transfer-request").unwrap(), Fr::from(100)];
+    expected.extend([0xaau8; 20].to_fields());
+    append_packed_bytes(b"thanks", 16, &mut expected);
+    assert_eq!(request.to_fields(), expected);
+
+    let cached = Transfer {
+        cached: Some(Fr::from(1)),
+        ..transfer()
+    };
+    assert_eq!(cached.poseidon_hash(), request.poseidon_hash());
+
+    assert_eq!(Pair(1u64, 2u64).to_fields(), vec![Fr::from(1), Fr::from(2)]);
+}
+
+#[test]
+fn test_field_domains() {
+    let ballot = Ballot {
+        voter: 7,
+        choice: b"yes".to_vec(),
+    };
+    let mut expected = vec![domain_tag(b"voter").unwrap(), Fr::from(7)];
+    expected.push(domain_tag(b"choice").unwrap());
+    append_packed_bytes(b"yes", 31, &mut expected);
+    assert_eq!(ballot.to_fields(), expected);
+    assert_ne!(
+        ballot.poseidon_hash(),
+        (7u64, b"yes".to_vec()).poseidon_hash()
+    );
+}
+
+#[test]
+fn test_enum_layout() {
+    assert_eq!(Action::Noop.to_fields(), vec![Fr::from(0)]);
+    assert_eq!(
+        Action::Vote {
+            proposal: 3,
+            approve: true
+        }
+        .to_fields(),
+        vec![Fr::from(1), Fr::from(3), Fr::from(1)]
+    );
+    let transfer_fields = transfer().to_fields();
+    assert_eq!(
+        Action::Transfer(transfer()).to_fields(),
+        [vec![Fr::from(2)], transfer_fields].concat()
+    );
+}
@@ -0,0 +1,9 @@
+use poseidon_derive::PoseidonHash;
+
+#[derive(PoseidonHash)]
+struct Packed {
+    #[poseidon(skip, pack_bytes = 16)]
+    bytes: Vec<u8>,
+}
+
+fn main() {}
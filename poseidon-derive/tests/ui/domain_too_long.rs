@@ -0,0 +1,9 @@
+use poseidon_derive::PoseidonHash;
+
+#[derive(PoseidonHash)]
+#[poseidon(domain = "a domain tag longer than 31 bytes")]
+struct Tagged {
+    value: u64,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use poseidon_derive::PoseidonHash;
+
+#[derive(PoseidonHash)]
+struct Tagged {
+    #[poseidon(domain = "first", domain = "second")]
+    value: u64,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use poseidon_derive::PoseidonHash;
+
+#[derive(PoseidonHash)]
+struct Packed {
+    #[poseidon(pack_bytes = 32)]
+    bytes: Vec<u8>,
+}
+
+fn main() {}
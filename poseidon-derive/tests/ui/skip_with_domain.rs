@@ -0,0 +1,9 @@
+use poseidon_derive::PoseidonHash;
+
+#[derive(PoseidonHash)]
+struct Skipped {
+    #[poseidon(skip, domain = "unused")]
+    cached: u64,
+}
+
+fn main() {}
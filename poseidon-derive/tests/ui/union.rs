@@ -0,0 +1,9 @@
+use poseidon_derive::PoseidonHash;
+
+#[derive(PoseidonHash)]
+union Bits {
+    int: u64,
+    float: f64,
+}
+
+fn main() {}
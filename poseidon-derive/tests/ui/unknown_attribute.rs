@@ -0,0 +1,9 @@
+use poseidon_derive::PoseidonHash;
+
+#[derive(PoseidonHash)]
+struct Unknown {
+    #[poseidon(rename = "other")]
+    value: u64,
+}
+
+fn main() {}
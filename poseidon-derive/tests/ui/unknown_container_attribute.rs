@@ -0,0 +1,9 @@
+use poseidon_derive::PoseidonHash;
+
+#[derive(PoseidonHash)]
+#[poseidon(skip)]
+struct Unknown {
+    value: u64,
+}
+
+fn main() {}
@@ -2,7 +2,7 @@ use criterion::{criterion_group, criterion_main, Criterion};
 
 use halo2curves::ff::*;
 
-use poseidon_rs::{Fr, Poseidon};
+use poseidon_rs::{poseidon_fields, poseidon_fields_batch_fixed, Fr, Poseidon};
 
 fn criterion_benchmark(c: &mut Criterion) {
     let b1: Fr = Fr::from_str_vartime(
@@ -21,6 +21,34 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("hash", |b| {
         b.iter(|| poseidon.hash(big_arr.clone()).unwrap())
     });
+
+    let leaves: Vec<[Fr; 3]> = (0..1024u64)
+        .map(|i| [Fr::from(i), Fr::from(i + 1), Fr::from(i + 2)])
+        .collect();
+    c.bench_function("hash 1024 leaves in a loop", |b| {
+        b.iter(|| {
+            leaves
+                .iter()
+                .map(|leaf| poseidon_fields(leaf).unwrap())
+                .collect::<Vec<_>>()
+        })
+    });
+    c.bench_function("hash 1024 leaves in a batch", |b| {
+        b.iter(|| poseidon_fields_batch_fixed(&leaves).unwrap())
+    });
+
+    let lanes: [[Fr; 2]; 4] = [[b1, b2]; 4];
+    c.bench_function("hash 4 inputs one by one", |b| {
+        b.iter(|| {
+            lanes
+                .iter()
+                .map(|input| poseidon.hash_slice(input).unwrap())
+                .collect::<Vec<_>>()
+        })
+    });
+    c.bench_function("hash 4 inputs in lanes", |b| {
+        b.iter(|| poseidon.hash_lanes(&lanes).unwrap())
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
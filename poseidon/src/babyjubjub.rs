@@ -0,0 +1,179 @@
+use crate::utils::fr_to_be_bytes;
+use crate::Fr;
+use halo2curves::ff::*;
+use num_bigint::BigUint;
+use once_cell::sync::OnceCell;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum BabyJubjubError {
+    #[error("Point is not on the curve")]
+    NotOnCurve,
+    #[error("Invalid compressed point")]
+    InvalidCompressedPoint,
+    #[error("Scalar is not below the subgroup order")]
+    ScalarOutOfRange,
+}
+
+/// `a` coefficient of the twisted Edwards equation `a x^2 + y^2 = 1 + d x^2 y^2`.
+pub const A: u64 = 168700;
+/// `d` coefficient of the twisted Edwards equation `a x^2 + y^2 = 1 + d x^2 y^2`.
+pub const D: u64 = 168696;
+
+/// Order of the prime subgroup generated by [`base8`].
+pub fn sub_order() -> &'static BigUint {
+    static SUB_ORDER: OnceCell<BigUint> = OnceCell::new();
+    SUB_ORDER.get_or_init(|| {
+        BigUint::parse_bytes(
+            b"2736030358979909402780800718157159386076813972158567259200215660948447373041",
+            10,
+        )
+        .unwrap()
+    })
+}
+
+/// Generator of the prime subgroup, `Base8` in circomlib.
+pub fn base8() -> Point {
+    Point {
+        x: Fr::from_str_vartime(
+            "5299619240641551281634865583518297030282874472190772894086521144482721001553",
+        )
+        .unwrap(),
+        y: Fr::from_str_vartime(
+            "16950150798460657717958625567821834550301663161624707787222815936182638968203",
+        )
+        .unwrap(),
+    }
+}
+
+/// Affine point of Baby Jubjub, the twisted Edwards curve over the BN254 scalar field.
+/// The coordinates are private so that every `Point` is on the curve, where [`Point::add`]
+/// is complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Point {
+    x: Fr,
+    y: Fr,
+}
+
+impl Point {
+    pub fn identity() -> Point {
+        Point {
+            x: Fr::ZERO,
+            y: Fr::ONE,
+        }
+    }
+
+    pub fn new(x: Fr, y: Fr) -> Result<Point, BabyJubjubError> {
+        let point = Point { x, y };
+        if !point.is_on_curve() {
+            return Err(BabyJubjubError::NotOnCurve);
+        }
+        Ok(point)
+    }
+
+    pub fn x(&self) -> Fr {
+        self.x
+    }
+
+    pub fn y(&self) -> Fr {
+        self.y
+    }
+
+    pub fn is_on_curve(&self) -> bool {
+        let x2 = self.x.square();
+        let y2 = self.y.square();
+        Fr::from(A) * x2 + y2 == Fr::ONE + Fr::from(D) * x2 * y2
+    }
+
+    pub fn in_sub_group(&self) -> bool {
+        self.is_on_curve() && self.mul_scalar(sub_order()) == Point::identity()
+    }
+
+    /// Complete addition of points on the curve, also valid for doubling.
+    pub fn add(&self, other: &Point) -> Point {
+        let x1x2 = self.x * other.x;
+        let y1y2 = self.y * other.y;
+        // `A` is a square and `D` is not, so `1 ± dxy` is never zero for points on the curve.
+        let dxy = Fr::from(D) * x1x2 * y1y2;
+        let x = (self.x * other.y + self.y * other.x) * (Fr::ONE + dxy).invert().unwrap();
+        let y = (y1y2 - Fr::from(A) * x1x2) * (Fr::ONE - dxy).invert().unwrap();
+        Point { x, y }
+    }
+
+    /// Double-and-add over the bits of `scalar`. Not constant-time: the running time depends
+    /// on the bit length and the bits of `scalar`, so it must not be used where an attacker can
+    /// time operations on a secret scalar.
+    pub fn mul_scalar(&self, scalar: &BigUint) -> Point {
+        let mut result = Point::identity();
+        for i in (0..scalar.bits()).rev() {
+            result = result.add(&result);
+            if scalar.bit(i) {
+                result = result.add(self);
+            }
+        }
+        result
+    }
+
+    /// circomlib `packPoint`: `y` in little-endian with the sign of `x` in the top bit.
+    pub fn compress(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(self.y.to_repr().as_ref());
+        if is_negative(&self.x) {
+            bytes[31] |= 0x80;
+        }
+        bytes
+    }
+
+    /// circomlib `unpackPoint`.
+    pub fn decompress(bytes: &[u8; 32]) -> Result<Point, BabyJubjubError> {
+        let sign = bytes[31] & 0x80 != 0;
+        let mut repr = <Fr as PrimeField>::Repr::default();
+        repr.as_mut().copy_from_slice(bytes);
+        repr.as_mut()[31] &= 0x7f;
+        let y: Option<Fr> = Fr::from_repr(repr).into();
+        let y = y.ok_or(BabyJubjubError::InvalidCompressedPoint)?;
+
+        let y2 = y.square();
+        let denominator: Option<Fr> = (Fr::from(A) - Fr::from(D) * y2).invert().into();
+        let denominator = denominator.ok_or(BabyJubjubError::InvalidCompressedPoint)?;
+        let x: Option<Fr> = ((Fr::ONE - y2) * denominator).sqrt().into();
+        let mut x = x.ok_or(BabyJubjubError::InvalidCompressedPoint)?;
+        if is_negative(&x) != sign {
+            x = -x;
+        }
+        Ok(Point { x, y })
+    }
+}
+
+/// Whether `field` is in the upper half of the field, i.e. greater than `(p - 1) / 2`.
+fn is_negative(field: &Fr) -> bool {
+    fr_to_be_bytes(field) > fr_to_be_bytes(&-Fr::TWO_INV)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_curve() {
+        let b8 = base8();
+        assert!(b8.is_on_curve());
+        assert!(b8.in_sub_group());
+        assert_eq!(b8.add(&Point::identity()), b8);
+        assert_eq!(b8.mul_scalar(&BigUint::from(3u32)), b8.add(&b8).add(&b8));
+        assert_eq!(
+            Point::new(Fr::ONE, Fr::ONE),
+            Err(BabyJubjubError::NotOnCurve)
+        );
+
+        for k in [1u32, 2, 3, 1000].iter() {
+            let point = b8.mul_scalar(&BigUint::from(*k));
+            assert_eq!(Point::decompress(&point.compress()), Ok(point));
+            let neg = Point {
+                x: -point.x,
+                y: point.y,
+            };
+            assert_eq!(Point::decompress(&neg.compress()), Ok(neg));
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! BLAKE-512, the SHA-3 finalist (not BLAKE2), used by circomlib for EdDSA key and nonce derivation.
+
+const IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+const C: [u64; 16] = [
+    0x243f6a8885a308d3,
+    0x13198a2e03707344,
+    0xa4093822299f31d0,
+    0x082efa98ec4e6c89,
+    0x452821e638d01377,
+    0xbe5466cf34e90c6c,
+    0xc0ac29b7c97c50dd,
+    0x3f84d5b5b5470917,
+    0x9216d5d98979fb1b,
+    0xd1310ba698dfb5ac,
+    0x2ffd72dbd01adfb7,
+    0xb8e1afed6a267e96,
+    0xba7c9045f12c7f99,
+    0x24a19947b3916cf7,
+    0x0801f2e2858efc16,
+    0x636920d871574e69,
+];
+
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+const ROUNDS: usize = 16;
+
+pub(crate) fn blake512(input: &[u8]) -> [u8; 64] {
+    let mut h = IV;
+    let bits = (input.len() as u128) * 8;
+
+    let full_blocks = input.len() / 128;
+    for (i, block) in input.chunks_exact(128).enumerate() {
+        compress(&mut h, block, ((i + 1) as u128) * 1024);
+    }
+
+    // Padding: a 1 bit, zeros, a 1 bit and the 128-bit length, over one or two blocks.
+    // Blocks without any message bit are compressed with a zero counter.
+    let rest = &input[full_blocks * 128..];
+    let mut tail = rest.to_vec();
+    tail.push(0x80);
+    let tail_len = if rest.len() < 112 { 128 } else { 256 };
+    tail.resize(tail_len - 16, 0);
+    *tail.last_mut().unwrap() |= 0x01;
+    tail.extend_from_slice(&bits.to_be_bytes());
+    for (i, block) in tail.chunks_exact(128).enumerate() {
+        let counter = if i == 0 && !rest.is_empty() { bits } else { 0 };
+        compress(&mut h, block, counter);
+    }
+
+    let mut digest = [0u8; 64];
+    for (chunk, word) in digest.chunks_exact_mut(8).zip(h.iter()) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn compress(h: &mut [u64; 8], block: &[u8], counter: u128) {
+    let mut m = [0u64; 16];
+    for (word, chunk) in m.iter_mut().zip(block.chunks_exact(8)) {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(chunk);
+        *word = u64::from_be_bytes(bytes);
+    }
+    let t0 = counter as u64;
+    let t1 = (counter >> 64) as u64;
+
+    let mut v = [0u64; 16];
+    v[..8].copy_from_slice(h);
+    v[8..12].copy_from_slice(&C[..4]);
+    v[12] = t0 ^ C[4];
+    v[13] = t0 ^ C[5];
+    v[14] = t1 ^ C[6];
+    v[15] = t1 ^ C[7];
+
+    for round in 0..ROUNDS {
+        let s = &SIGMA[round % 10];
+        g(&mut v, &m, s, 0, [0, 4, 8, 12]);
+        g(&mut v, &m, s, 1, [1, 5, 9, 13]);
+        g(&mut v, &m, s, 2, [2, 6, 10, 14]);
+        g(&mut v, &m, s, 3, [3, 7, 11, 15]);
+        g(&mut v, &m, s, 4, [0, 5, 10, 15]);
+        g(&mut v, &m, s, 5, [1, 6, 11, 12]);
+        g(&mut v, &m, s, 6, [2, 7, 8, 13]);
+        g(&mut v, &m, s, 7, [3, 4, 9, 14]);
+    }
+
+    for i in 0..8 {
+        h[i] ^= v[i] ^ v[i + 8];
+    }
+}
+
+fn g(v: &mut [u64; 16], m: &[u64; 16], s: &[usize; 16], i: usize, [a, b, c, d]: [usize; 4]) {
+    let (x, y) = (s[2 * i], s[2 * i + 1]);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(m[x] ^ C[y]);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(25);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(m[y] ^ C[x]);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(11);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_to_bytes(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_blake512() {
+        let cases: [(&[u8], &str); 3] = [
+            (
+                &[],
+                "a8cfbbd73726062df0c6864dda65defe58ef0cc52a5625090fa17601e1eecd1b\
+                 628e94f396ae402a00acc9eab77b4d4c2e852aaaa25a636d80af3fc7913ef5b8",
+            ),
+            (
+                &[0],
+                "97961587f6d970faba6d2478045de6d1fabd09b61ae50932054d52bc29d31be4\
+                 ff9102b9f69e2bbdb83be13d4b9c06091e5fa0b48bd081b634058be0ec49beb3",
+            ),
+            (
+                &[0; 144],
+                "313717d608e9cf758dcb1eb0f0c3cf9fc150b2d500fb33f51c52afc99d358a2f\
+                 1374b8a38bba7974e7f6ef79cab16f22ce1e649d6e01ad9589c213045d545dde",
+            ),
+        ];
+        for (input, expected) in cases.iter() {
+            assert_eq!(blake512(input).to_vec(), hex_to_bytes(expected));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{poseidon_default, Fr, PoseidonError};
+use halo2curves::ff::*;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CipherError {
+    #[error("Wrong ciphertext length: expected `{0}` but got `{1}`")]
+    WrongCiphertextLength(usize, usize),
+    #[error("Message length `{0}` is too large")]
+    LengthOverflow(usize),
+    #[error("Invalid padding of the decrypted message")]
+    InvalidPadding,
+    #[error("Invalid authentication tag")]
+    InvalidTag,
+    #[error(transparent)]
+    Poseidon(#[from] PoseidonError),
+}
+
+/// Encrypts `message` with a duplex sponge over the t=4 permutation, compatible with
+/// `poseidonEncrypt` of MACI and zk-kit. `key` is typically an ECDH shared point.
+/// The ciphertext holds the message padded with zeros to a multiple of 3, followed by the tag.
+pub fn poseidon_encrypt(
+    message: &[Fr],
+    key: &[Fr; 2],
+    nonce: u128,
+) -> Result<Vec<Fr>, PoseidonError> {
+    let poseidon = poseidon_default();
+    let mut padded = message.to_vec();
+    // A slice of field elements is far shorter than `usize::MAX`.
+    padded.resize(padded_len(message.len()).unwrap(), Fr::ZERO);
+
+    let mut state = initial_state(key, nonce, message.len());
+    let mut ciphertext = Vec::with_capacity(padded.len() + 1);
+    for chunk in padded.chunks(3) {
+        poseidon.permute(&mut state)?;
+        for (s, m) in state[1..].iter_mut().zip(chunk.iter()) {
+            *s += m;
+            ciphertext.push(*s);
+        }
+    }
+    poseidon.permute(&mut state)?;
+    ciphertext.push(state[1]);
+    Ok(ciphertext)
+}
+
+/// Decrypts a ciphertext produced by [`poseidon_encrypt`] for a message of `length` elements,
+/// rejecting it if the padding or the authentication tag do not match.
+pub fn poseidon_decrypt(
+    ciphertext: &[Fr],
+    key: &[Fr; 2],
+    nonce: u128,
+    length: usize,
+) -> Result<Vec<Fr>, CipherError> {
+    let poseidon = poseidon_default();
+    let expected_len = padded_len(length)
+        .and_then(|len| len.checked_add(1))
+        .ok_or(CipherError::LengthOverflow(length))?;
+    if ciphertext.len() != expected_len {
+        return Err(CipherError::WrongCiphertextLength(
+            expected_len,
+            ciphertext.len(),
+        ));
+    }
+
+    let mut state = initial_state(key, nonce, length);
+    let mut message = Vec::with_capacity(expected_len - 1);
+    for chunk in ciphertext[..expected_len - 1].chunks(3) {
+        poseidon.permute(&mut state)?;
+        for (s, c) in state[1..].iter_mut().zip(chunk.iter()) {
+            message.push(*c - *s);
+            *s = *c;
+        }
+    }
+    if message[length..].iter().any(|m| *m != Fr::ZERO) {
+        return Err(CipherError::InvalidPadding);
+    }
+    poseidon.permute(&mut state)?;
+    if ciphertext[expected_len - 1] != state[1] {
+        return Err(CipherError::InvalidTag);
+    }
+    message.truncate(length);
+    Ok(message)
+}
+
+/// `length` rounded up to a multiple of 3, the rate of the sponge, `None` on overflow.
+fn padded_len(length: usize) -> Option<usize> {
+    length.checked_add((3 - length % 3) % 3)
+}
+
+/// `[0, key[0], key[1], nonce + length * 2^128]`.
+fn initial_state(key: &[Fr; 2], nonce: u128, length: usize) -> Vec<Fr> {
+    let two_128 = Fr::from_u128(u128::MAX) + Fr::ONE;
+    vec![
+        Fr::ZERO,
+        key[0],
+        key[1],
+        Fr::from_u128(nonce) + Fr::from(length as u64) * two_128,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> [Fr; 2] {
+        [Fr::from(123456789), Fr::from(987654321)]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for length in 0..8 {
+            let message: Vec<Fr> = (0..length).map(|i| Fr::from(i as u64 + 1)).collect();
+            let ciphertext = poseidon_encrypt(&message, &key(), 42).unwrap();
+            assert_eq!(ciphertext.len(), padded_len(length).unwrap() + 1);
+            let decrypted = poseidon_decrypt(&ciphertext, &key(), 42, length).unwrap();
+            assert_eq!(decrypted, message);
+        }
+    }
+
+    #[test]
+    fn test_reject_tampering() {
+        let message = vec![Fr::from(1), Fr::from(2), Fr::from(3), Fr::from(4)];
+        let ciphertext = poseidon_encrypt(&message, &key(), 7).unwrap();
+
+        for i in 0..ciphertext.len() {
+            let mut tampered = ciphertext.clone();
+            tampered[i] += Fr::ONE;
+            poseidon_decrypt(&tampered, &key(), 7, 4).expect_err("Tampered ciphertext");
+        }
+        poseidon_decrypt(&ciphertext, &key(), 8, 4).expect_err("Wrong nonce");
+        poseidon_decrypt(&ciphertext, &[Fr::ONE, Fr::ONE], 7, 4).expect_err("Wrong key");
+        poseidon_decrypt(&ciphertext, &key(), 7, 5).expect_err("Wrong length");
+        poseidon_decrypt(&ciphertext[..6], &key(), 7, 4).expect_err("Truncated ciphertext");
+
+        // The expected length must not wrap around to match a short ciphertext.
+        for length in [usize::MAX, usize::MAX - 1, usize::MAX - 2] {
+            for ciphertext in [&[][..], &ciphertext[..]] {
+                assert!(matches!(
+                    poseidon_decrypt(ciphertext, &key(), 7, length),
+                    Err(CipherError::LengthOverflow(_))
+                ));
+            }
+        }
+    }
+}
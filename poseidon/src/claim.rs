@@ -0,0 +1,456 @@
+//! iden3 claims with the slot layout of go-iden3-core.
+//!
+//! ```text
+//! i_0: [128 bits] schema hash, [32 bits] flags, [32 bits] version
+//! i_1: [248 bits] identity when stored in the index
+//! i_2, i_3: data slots, i_2 also holds a merklized root stored in the index
+//! v_0: [64 bits] revocation nonce, [64 bits] expiration date
+//! v_1: [248 bits] identity when stored in the value
+//! v_2, v_3: data slots, v_2 also holds a merklized root stored in the value
+//! ```
+
+use crate::{poseidon_fields, Fr};
+use halo2curves::ff::*;
+use num_bigint::BigUint;
+use serde::de::Error as DeError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryInto;
+use thiserror::Error;
+
+const FLAGS_BYTE: usize = 16;
+const SUBJECT_MASK: u8 = 0b0000_0111;
+const EXPIRATION_BIT: u8 = 3;
+const UPDATABLE_BIT: u8 = 4;
+const MERKLIZED_MASK: u8 = 0b1110_0000;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ClaimError {
+    #[error("Invalid hex: {0}")]
+    InvalidHex(String),
+    #[error("Wrong claim length: expected `{0}` slots but got `{1}`")]
+    WrongLength(usize, usize),
+    #[error("Slot `{0}` is not a valid field element")]
+    InvalidSlot(usize),
+    #[error("Invalid subject flag bits `{0:#05b}`")]
+    InvalidIdPosition(u8),
+    #[error("Invalid merklized flag bits `{0:#05b}`")]
+    InvalidMerklizedRootPosition(u8),
+}
+
+/// Where the identity the claim is about is stored, if any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdPosition {
+    None,
+    Index,
+    Value,
+}
+
+/// Where the root of merklized claim data is stored, if any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MerklizedRootPosition {
+    None,
+    Index,
+    Value,
+}
+
+/// Claim of 8 slots, 4 index slots hashed into `hi` and 4 value slots hashed into `hv`.
+/// Slots are little-endian field element representations, as in go-iden3-core.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Claim {
+    index: [[u8; 32]; 4],
+    value: [[u8; 32]; 4],
+}
+
+impl Claim {
+    pub fn new(schema_hash: [u8; 16]) -> Claim {
+        let mut claim = Claim {
+            index: [[0; 32]; 4],
+            value: [[0; 32]; 4],
+        };
+        claim.set_schema_hash(schema_hash);
+        claim
+    }
+
+    pub fn schema_hash(&self) -> [u8; 16] {
+        self.index[0][..16].try_into().unwrap()
+    }
+
+    pub fn set_schema_hash(&mut self, schema_hash: [u8; 16]) {
+        self.index[0][..16].copy_from_slice(&schema_hash);
+    }
+
+    pub fn version(&self) -> u32 {
+        u32::from_le_bytes(self.index[0][20..24].try_into().unwrap())
+    }
+
+    pub fn set_version(&mut self, version: u32) {
+        self.index[0][20..24].copy_from_slice(&version.to_le_bytes());
+    }
+
+    pub fn revocation_nonce(&self) -> u64 {
+        u64::from_le_bytes(self.value[0][..8].try_into().unwrap())
+    }
+
+    pub fn set_revocation_nonce(&mut self, nonce: u64) {
+        self.value[0][..8].copy_from_slice(&nonce.to_le_bytes());
+    }
+
+    /// Expiration date as a Unix timestamp, `None` if the expiration flag is not set.
+    pub fn expiration_date(&self) -> Option<u64> {
+        if self.flag(EXPIRATION_BIT) {
+            Some(u64::from_le_bytes(self.value[0][8..16].try_into().unwrap()))
+        } else {
+            None
+        }
+    }
+
+    pub fn set_expiration_date(&mut self, expiration: Option<u64>) {
+        self.set_flag(EXPIRATION_BIT, expiration.is_some());
+        self.value[0][8..16].copy_from_slice(&expiration.unwrap_or(0).to_le_bytes());
+    }
+
+    pub fn updatable(&self) -> bool {
+        self.flag(UPDATABLE_BIT)
+    }
+
+    pub fn set_updatable(&mut self, updatable: bool) {
+        self.set_flag(UPDATABLE_BIT, updatable);
+    }
+
+    /// Fails on subject flag bits that go-iden3-core does not define.
+    pub fn id_position(&self) -> Result<IdPosition, ClaimError> {
+        match self.index[0][FLAGS_BYTE] & SUBJECT_MASK {
+            0b000 => Ok(IdPosition::None),
+            0b010 => Ok(IdPosition::Index),
+            0b011 => Ok(IdPosition::Value),
+            bits => Err(ClaimError::InvalidIdPosition(bits)),
+        }
+    }
+
+    pub fn id(&self) -> Result<Option<[u8; 31]>, ClaimError> {
+        let slot = match self.id_position()? {
+            IdPosition::None => return Ok(None),
+            IdPosition::Index => &self.index[1],
+            IdPosition::Value => &self.value[1],
+        };
+        Ok(Some(slot[..31].try_into().unwrap()))
+    }
+
+    /// Stores `id` in the index, so that the identity is part of `hi`.
+    pub fn set_index_id(&mut self, id: [u8; 31]) {
+        self.reset_id();
+        self.set_subject(0b010);
+        self.index[1][..31].copy_from_slice(&id);
+    }
+
+    pub fn set_value_id(&mut self, id: [u8; 31]) {
+        self.reset_id();
+        self.set_subject(0b011);
+        self.value[1][..31].copy_from_slice(&id);
+    }
+
+    pub fn reset_id(&mut self) {
+        self.set_subject(0);
+        self.index[1] = [0; 32];
+        self.value[1] = [0; 32];
+    }
+
+    /// Fails on merklized flag bits that go-iden3-core does not define.
+    pub fn merklized_root_position(&self) -> Result<MerklizedRootPosition, ClaimError> {
+        match self.index[0][FLAGS_BYTE] & MERKLIZED_MASK {
+            0b0000_0000 => Ok(MerklizedRootPosition::None),
+            0b0010_0000 => Ok(MerklizedRootPosition::Index),
+            0b0100_0000 => Ok(MerklizedRootPosition::Value),
+            bits => Err(ClaimError::InvalidMerklizedRootPosition(bits >> 5)),
+        }
+    }
+
+    pub fn merklized_root(&self) -> Result<Option<Fr>, ClaimError> {
+        Ok(match self.merklized_root_position()? {
+            MerklizedRootPosition::None => None,
+            MerklizedRootPosition::Index => Some(slot_to_fr(&self.index[2])),
+            MerklizedRootPosition::Value => Some(slot_to_fr(&self.value[2])),
+        })
+    }
+
+    pub fn set_index_merklized_root(&mut self, root: Fr) {
+        self.reset_merklized_root();
+        self.set_merklized(0b0010_0000);
+        self.index[2] = fr_to_slot(&root);
+    }
+
+    pub fn set_value_merklized_root(&mut self, root: Fr) {
+        self.reset_merklized_root();
+        self.set_merklized(0b0100_0000);
+        self.value[2] = fr_to_slot(&root);
+    }
+
+    pub fn reset_merklized_root(&mut self) {
+        self.set_merklized(0);
+        self.index[2] = [0; 32];
+        self.value[2] = [0; 32];
+    }
+
+    /// Sets the index data slots `i_2` and `i_3`.
+    pub fn set_index_data(&mut self, slot_a: Fr, slot_b: Fr) {
+        self.index[2] = fr_to_slot(&slot_a);
+        self.index[3] = fr_to_slot(&slot_b);
+    }
+
+    /// Sets the value data slots `v_2` and `v_3`.
+    pub fn set_value_data(&mut self, slot_a: Fr, slot_b: Fr) {
+        self.value[2] = fr_to_slot(&slot_a);
+        self.value[3] = fr_to_slot(&slot_b);
+    }
+
+    /// The 8 slots as field elements, index slots first.
+    pub fn slots(&self) -> [Fr; 8] {
+        let mut slots = [Fr::ZERO; 8];
+        for (slot, bytes) in slots
+            .iter_mut()
+            .zip(self.index.iter().chain(self.value.iter()))
+        {
+            *slot = slot_to_fr(bytes);
+        }
+        slots
+    }
+
+    /// `Poseidon(i_0, i_1, i_2, i_3)`, the key of the claim in a claims tree.
+    pub fn hi(&self) -> Fr {
+        poseidon_fields(&self.slots()[..4]).unwrap()
+    }
+
+    /// `Poseidon(v_0, v_1, v_2, v_3)`.
+    pub fn hv(&self) -> Fr {
+        poseidon_fields(&self.slots()[4..]).unwrap()
+    }
+
+    /// `Poseidon(hi, hv)`, the message signed by issuers (`getClaimHash` in the iden3 circuits).
+    pub fn hash(&self) -> Fr {
+        poseidon_fields(&[self.hi(), self.hv()]).unwrap()
+    }
+
+    /// Hex of the 256 bytes of the slots, index first, without `0x` as in go-iden3-core.
+    pub fn to_hex(&self) -> String {
+        let mut hex = String::with_capacity(512);
+        for slot in self.index.iter().chain(self.value.iter()) {
+            for byte in slot.iter() {
+                hex.push_str(&format!("{:02x}", byte));
+            }
+        }
+        hex
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Claim, ClaimError> {
+        if hex.len() != 512 {
+            return Err(ClaimError::InvalidHex(format!(
+                "expected 512 hex characters but got {}",
+                hex.len()
+            )));
+        }
+        if let Some(c) = hex.chars().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(ClaimError::InvalidHex(format!(
+                "invalid hex character {:?}",
+                c
+            )));
+        }
+        let mut slots = [[0u8; 32]; 8];
+        for (i, byte) in slots.iter_mut().flatten().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+                .map_err(|e| ClaimError::InvalidHex(e.to_string()))?;
+        }
+        Claim::from_slots(slots)
+    }
+
+    fn from_slots(slots: [[u8; 32]; 8]) -> Result<Claim, ClaimError> {
+        for (i, slot) in slots.iter().enumerate() {
+            if repr_to_fr(slot).is_none() {
+                return Err(ClaimError::InvalidSlot(i));
+            }
+        }
+        Ok(Claim {
+            index: slots[..4].try_into().unwrap(),
+            value: slots[4..].try_into().unwrap(),
+        })
+    }
+
+    fn flag(&self, bit: u8) -> bool {
+        self.index[0][FLAGS_BYTE] & (1 << bit) != 0
+    }
+
+    fn set_flag(&mut self, bit: u8, value: bool) {
+        if value {
+            self.index[0][FLAGS_BYTE] |= 1 << bit;
+        } else {
+            self.index[0][FLAGS_BYTE] &= !(1 << bit);
+        }
+    }
+
+    fn set_subject(&mut self, subject: u8) {
+        self.index[0][FLAGS_BYTE] = (self.index[0][FLAGS_BYTE] & !SUBJECT_MASK) | subject;
+    }
+
+    fn set_merklized(&mut self, merklized: u8) {
+        self.index[0][FLAGS_BYTE] = (self.index[0][FLAGS_BYTE] & !MERKLIZED_MASK) | merklized;
+    }
+}
+
+/// JSON array of the 8 slots as decimal strings, as go-iden3-core marshals claims.
+impl Serialize for Claim {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let slots: Vec<String> = self
+            .index
+            .iter()
+            .chain(self.value.iter())
+            .map(|slot| BigUint::from_bytes_le(slot).to_string())
+            .collect();
+        slots.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Claim {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Claim, D::Error> {
+        let decimals = Vec::<String>::deserialize(deserializer)?;
+        if decimals.len() != 8 {
+            return Err(D::Error::custom(ClaimError::WrongLength(8, decimals.len())));
+        }
+        let mut slots = [[0u8; 32]; 8];
+        for (i, (slot, decimal)) in slots.iter_mut().zip(decimals.iter()).enumerate() {
+            let bytes = BigUint::parse_bytes(decimal.as_bytes(), 10)
+                .map(|value| value.to_bytes_le())
+                .filter(|bytes| bytes.len() <= 32)
+                .ok_or_else(|| D::Error::custom(ClaimError::InvalidSlot(i)))?;
+            slot[..bytes.len()].copy_from_slice(&bytes);
+        }
+        Claim::from_slots(slots).map_err(D::Error::custom)
+    }
+}
+
+fn slot_to_fr(slot: &[u8; 32]) -> Fr {
+    // Slots are always canonical: setters take field elements or fixed-size fields,
+    // and parsing rejects out of range values.
+    repr_to_fr(slot).unwrap()
+}
+
+fn repr_to_fr(slot: &[u8; 32]) -> Option<Fr> {
+    let mut repr = <Fr as PrimeField>::Repr::default();
+    repr.as_mut().copy_from_slice(slot);
+    Fr::from_repr(repr).into()
+}
+
+fn fr_to_slot(field: &Fr) -> [u8; 32] {
+    let mut slot = [0u8; 32];
+    slot.copy_from_slice(field.to_repr().as_ref());
+    slot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claim() -> Claim {
+        let mut claim = Claim::new([0xab; 16]);
+        claim.set_version(7);
+        claim.set_revocation_nonce(0x0102030405060708);
+        claim.set_expiration_date(Some(1_700_000_000));
+        claim.set_updatable(true);
+        claim.set_index_id([0x11; 31]);
+        claim.set_value_data(Fr::from(5), -Fr::ONE);
+        claim
+    }
+
+    #[test]
+    fn test_claim_layout() {
+        let claim = claim();
+        assert_eq!(claim.schema_hash(), [0xab; 16]);
+        assert_eq!(claim.version(), 7);
+        assert_eq!(claim.revocation_nonce(), 0x0102030405060708);
+        assert_eq!(claim.expiration_date(), Some(1_700_000_000));
+        assert!(claim.updatable());
+        assert_eq!(claim.id_position(), Ok(IdPosition::Index));
+        assert_eq!(claim.id(), Ok(Some([0x11; 31])));
+        assert_eq!(
+            claim.merklized_root_position(),
+            Ok(MerklizedRootPosition::None)
+        );
+
+        // Flags byte: subject 010, expiration bit 3 and updatable bit 4.
+        assert_eq!(claim.index[0][FLAGS_BYTE], 0b0001_1010);
+        assert_eq!(claim.index[0][20..24], [7, 0, 0, 0]);
+        assert_eq!(claim.value[0][..8], [8, 7, 6, 5, 4, 3, 2, 1]);
+
+        let mut moved = claim.clone();
+        moved.set_value_id([0x22; 31]);
+        assert_eq!(moved.id_position(), Ok(IdPosition::Value));
+        assert_eq!(moved.index[1], [0; 32]);
+        assert_eq!(moved.id(), Ok(Some([0x22; 31])));
+        moved.set_expiration_date(None);
+        assert_eq!(moved.expiration_date(), None);
+        moved.set_value_merklized_root(Fr::from(9));
+        assert_eq!(moved.merklized_root(), Ok(Some(Fr::from(9))));
+        assert_eq!(moved.index[0][FLAGS_BYTE], 0b0101_0011);
+
+        // Undefined flag bits are errors rather than "no identity" or "no root".
+        let mut invalid = claim.clone();
+        invalid.index[0][FLAGS_BYTE] = 0b0110_0001;
+        assert_eq!(invalid.id_position(), Err(ClaimError::InvalidIdPosition(1)));
+        assert_eq!(invalid.id(), Err(ClaimError::InvalidIdPosition(1)));
+        assert_eq!(
+            invalid.merklized_root_position(),
+            Err(ClaimError::InvalidMerklizedRootPosition(0b011))
+        );
+        assert_eq!(
+            invalid.merklized_root(),
+            Err(ClaimError::InvalidMerklizedRootPosition(0b011))
+        );
+    }
+
+    #[test]
+    fn test_claim_hash() {
+        let claim = claim();
+        let slots = claim.slots();
+        assert_eq!(slots[6], Fr::from(5));
+        assert_eq!(slots[7], -Fr::ONE);
+        let hi = poseidon_fields(&slots[..4]).unwrap();
+        let hv = poseidon_fields(&slots[4..]).unwrap();
+        assert_eq!(claim.hi(), hi);
+        assert_eq!(claim.hv(), hv);
+        assert_eq!(claim.hash(), poseidon_fields(&[hi, hv]).unwrap());
+
+        let mut other = claim.clone();
+        other.set_revocation_nonce(1);
+        assert_eq!(other.hi(), hi);
+        assert_ne!(other.hv(), hv);
+    }
+
+    #[test]
+    fn test_claim_serialization() {
+        let claim = claim();
+        let hex = claim.to_hex();
+        assert_eq!(hex.len(), 512);
+        assert!(hex.starts_with(&"ab".repeat(16)));
+        assert_eq!(Claim::from_hex(&hex), Ok(claim.clone()));
+        Claim::from_hex(&hex[2..]).expect_err("Too short");
+        let mut signed = hex.clone();
+        signed.replace_range(..2, "+a");
+        assert!(matches!(
+            Claim::from_hex(&signed),
+            Err(ClaimError::InvalidHex(_))
+        ));
+        let mut out_of_range = hex.clone();
+        out_of_range.replace_range(448.., &"ff".repeat(32));
+        assert_eq!(
+            Claim::from_hex(&out_of_range),
+            Err(ClaimError::InvalidSlot(7))
+        );
+
+        let json = serde_json::to_string(&claim).unwrap();
+        assert!(json.starts_with("[\""));
+        assert!(json.ends_with(&format!(
+            ",\"5\",\"{}\"]",
+            BigUint::from_bytes_le((-Fr::ONE).to_repr().as_ref())
+        )));
+        assert_eq!(serde_json::from_str::<Claim>(&json).unwrap(), claim);
+        serde_json::from_str::<Claim>("[\"1\",\"2\"]").expect_err("Wrong length");
+    }
+}
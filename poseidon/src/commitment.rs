@@ -0,0 +1,129 @@
+use crate::sponge::domain_tag;
+use crate::{poseidon_fields, Fr, PoseidonError};
+use serde::{Deserialize, Serialize};
+
+/// Domain of [`commit`], the first input of the hash.
+pub const COMMITMENT_DOMAIN: &[u8] = b"poseidon-rs/commitment";
+
+/// Maximum number of committed values: the domain tag and the blinding factor
+/// take two of the 16 inputs of a single Poseidon hash.
+pub const MAX_COMMITTED_VALUES: usize = 14;
+
+/// Hiding commitment `Poseidon(tag, blinding, values...)`. The hash width depends on
+/// the number of values, so commitments to different numbers of values never collide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Commitment(#[serde(with = "crate::serde_fr")] pub Fr);
+
+/// Values and blinding factor opening a [`Commitment`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Opening {
+    #[serde(with = "crate::serde_fr::vec")]
+    pub values: Vec<Fr>,
+    #[serde(with = "crate::serde_fr")]
+    pub blinding: Fr,
+}
+
+/// Commits to at most [`MAX_COMMITTED_VALUES`] values. `blinding` must be uniformly random
+/// and kept secret until the commitment is opened.
+pub fn commit(values: &[Fr], blinding: Fr) -> Result<Commitment, PoseidonError> {
+    if values.len() > MAX_COMMITTED_VALUES {
+        return Err(PoseidonError::WrongInputsLength(
+            MAX_COMMITTED_VALUES,
+            values.len(),
+        ));
+    }
+    let mut inputs = vec![domain_tag(COMMITMENT_DOMAIN)?, blinding];
+    inputs.extend_from_slice(values);
+    Ok(Commitment(poseidon_fields(&inputs)?))
+}
+
+pub fn verify(commitment: &Commitment, values: &[Fr], blinding: Fr) -> bool {
+    matches!(commit(values, blinding), Ok(expected) if expected == *commitment)
+}
+
+impl Opening {
+    pub fn new(values: Vec<Fr>, blinding: Fr) -> Opening {
+        Opening { values, blinding }
+    }
+
+    pub fn commitment(&self) -> Result<Commitment, PoseidonError> {
+        commit(&self.values, self.blinding)
+    }
+
+    pub fn verify(&self, commitment: &Commitment) -> bool {
+        verify(commitment, &self.values, self.blinding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use halo2curves::ff::*;
+
+    #[test]
+    fn test_commitment() {
+        let values = vec![Fr::from(1), Fr::from(2)];
+        let blinding = Fr::from(12345);
+        let commitment = commit(&values, blinding).unwrap();
+        assert!(verify(&commitment, &values, blinding));
+        assert!(!verify(&commitment, &values, blinding + Fr::ONE));
+        assert!(!verify(&commitment, &values[..1], blinding));
+        assert!(!verify(
+            &commitment,
+            &[values[0], values[1], Fr::ZERO],
+            blinding
+        ));
+        assert_eq!(
+            commitment.0,
+            poseidon_fields(&[
+                domain_tag(COMMITMENT_DOMAIN).unwrap(),
+                blinding,
+                values[0],
+                values[1]
+            ])
+            .unwrap()
+        );
+
+        commit(&[Fr::ZERO; MAX_COMMITTED_VALUES], blinding).unwrap();
+        commit(&[Fr::ZERO; MAX_COMMITTED_VALUES + 1], blinding).expect_err("Too many values");
+        assert!(!verify(
+            &commitment,
+            &[Fr::ZERO; MAX_COMMITTED_VALUES + 1],
+            blinding
+        ));
+    }
+
+    #[test]
+    fn test_opening_serde() {
+        let opening = Opening::new(vec![Fr::from(1), -Fr::ONE], Fr::from(0xabcdef));
+        let commitment = opening.commitment().unwrap();
+        assert!(opening.verify(&commitment));
+
+        let json = serde_json::to_string(&opening).unwrap();
+        assert_eq!(
+            json,
+            "{\"values\":[\
+             \"0x0000000000000000000000000000000000000000000000000000000000000001\",\
+             \"0x30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000000\"],\
+             \"blinding\":\"0x0000000000000000000000000000000000000000000000000000000000abcdef\"}"
+        );
+        assert_eq!(serde_json::from_str::<Opening>(&json).unwrap(), opening);
+
+        let json = serde_json::to_string(&commitment).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Commitment>(&json).unwrap(),
+            commitment
+        );
+        assert_eq!(
+            serde_json::from_str::<Commitment>("\"0xabc\"").unwrap(),
+            Commitment(Fr::from(0xabc))
+        );
+        serde_json::from_str::<Commitment>(
+            "\"0x30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000001\"",
+        )
+        .expect_err("Non-canonical");
+        serde_json::from_str::<Commitment>("\"0xzz\"").expect_err("Invalid hex");
+        serde_json::from_str::<Commitment>("\"0x+a\"").expect_err("Sign");
+        serde_json::from_str::<Commitment>("\"0x1+a\"").expect_err("Inner sign");
+    }
+}
@@ -0,0 +1,288 @@
+use crate::{compose_and_poseidon, Fr, PoseidonError};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use halo2curves::ff::*;
+use num_bigint::BigUint;
+use thiserror::Error;
+
+/// Bits per limb of the modulus in zk-email's `EmailVerifier` circuit.
+pub const LIMB_BITS: usize = 121;
+/// Number of limbs of the modulus, enough for 2048-bit keys.
+pub const NUM_LIMBS: usize = 17;
+
+/// DER encoding of the rsaEncryption OID, 1.2.840.113549.1.1.1.
+const RSA_ENCRYPTION_OID: [u8; 9] = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+
+#[derive(Error, Debug)]
+pub enum DkimError {
+    #[error("Invalid PEM: {0}")]
+    InvalidPem(&'static str),
+    #[error("Invalid DER: {0}")]
+    InvalidDer(&'static str),
+    #[error("Invalid DKIM record: {0}")]
+    InvalidRecord(&'static str),
+    #[error(transparent)]
+    Base64(#[from] base64::DecodeError),
+    #[error("Modulus too large: max `{0}` bits but got `{1}`")]
+    ModulusTooLarge(usize, u64),
+    #[error(transparent)]
+    Poseidon(#[from] PoseidonError),
+}
+
+/// Poseidon hash of the big-endian RSA `modulus` as computed by `EmailVerifier`:
+/// [`NUM_LIMBS`] limbs of [`LIMB_BITS`] bits hashed with `PoseidonLarge`,
+/// which packs pairs of limbs into one input.
+pub fn public_key_hash(modulus: &[u8]) -> Result<Fr, DkimError> {
+    let limbs = modulus_limbs(modulus)?;
+    Ok(compose_and_poseidon(&limbs, 2, LIMB_BITS as u128)?)
+}
+
+/// [`public_key_hash`] of a key in any format accepted by [`rsa_modulus`].
+pub fn public_key_hash_from_key(key: &str) -> Result<Fr, DkimError> {
+    public_key_hash(&rsa_modulus(key)?)
+}
+
+/// Little-endian limbs of the big-endian `modulus`, the `pubkey` input of `EmailVerifier`.
+pub fn modulus_limbs(modulus: &[u8]) -> Result<Vec<Fr>, DkimError> {
+    let modulus = BigUint::from_bytes_be(modulus);
+    if modulus.bits() > (LIMB_BITS * NUM_LIMBS) as u64 {
+        return Err(DkimError::ModulusTooLarge(
+            LIMB_BITS * NUM_LIMBS,
+            modulus.bits(),
+        ));
+    }
+    let mask = (BigUint::from(1u32) << LIMB_BITS) - 1u32;
+    Ok((0..NUM_LIMBS)
+        .map(|i| {
+            let limb = (&modulus >> (i * LIMB_BITS)) & &mask;
+            let mut repr = <Fr as PrimeField>::Repr::default();
+            let bytes = limb.to_bytes_le();
+            repr.as_mut()[..bytes.len()].copy_from_slice(&bytes);
+            Fr::from_repr(repr).unwrap()
+        })
+        .collect())
+}
+
+/// Big-endian modulus of an RSA public key given as PEM or as a DKIM DNS TXT record.
+pub fn rsa_modulus(key: &str) -> Result<Vec<u8>, DkimError> {
+    if key.trim_start().starts_with("-----BEGIN") {
+        rsa_modulus_from_pem(key)
+    } else {
+        rsa_modulus_from_dns_record(key)
+    }
+}
+
+/// Accepts `PUBLIC KEY` (SubjectPublicKeyInfo) and `RSA PUBLIC KEY` (PKCS#1) blocks.
+pub fn rsa_modulus_from_pem(pem: &str) -> Result<Vec<u8>, DkimError> {
+    let pem = pem.trim();
+    let label = pem
+        .strip_prefix("-----BEGIN ")
+        .and_then(|rest| rest.split("-----").next())
+        .ok_or(DkimError::InvalidPem("missing BEGIN line"))?;
+    if label != "PUBLIC KEY" && label != "RSA PUBLIC KEY" {
+        return Err(DkimError::InvalidPem("not a public key"));
+    }
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let body = pem
+        .strip_prefix(begin.as_str())
+        .ok_or(DkimError::InvalidPem("missing BEGIN line"))?
+        .strip_suffix(end.as_str())
+        .ok_or(DkimError::InvalidPem("missing END line"))?;
+    let body: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+    rsa_modulus_from_der(&STANDARD.decode(body)?)
+}
+
+/// Accepts a SubjectPublicKeyInfo or a PKCS#1 `RSAPublicKey`.
+pub fn rsa_modulus_from_der(der: &[u8]) -> Result<Vec<u8>, DkimError> {
+    let (key, rest) = read_tlv(der, 0x30)?;
+    if !rest.is_empty() {
+        return Err(DkimError::InvalidDer("trailing data"));
+    }
+    if key.first() == Some(&0x02) {
+        return read_modulus(key);
+    }
+
+    let (algorithm, rest) = read_tlv(key, 0x30)?;
+    let (oid, _) = read_tlv(algorithm, 0x06)?;
+    if oid != RSA_ENCRYPTION_OID {
+        return Err(DkimError::InvalidDer("not an RSA key"));
+    }
+    let (bits, _) = read_tlv(rest, 0x03)?;
+    match bits.split_first() {
+        Some((0, rsa_key)) => {
+            let (rsa_key, _) = read_tlv(rsa_key, 0x30)?;
+            read_modulus(rsa_key)
+        }
+        _ => Err(DkimError::InvalidDer("invalid bit string")),
+    }
+}
+
+/// Reads the base64 `p=` tag of a DKIM record such as `v=DKIM1; k=rsa; p=MIIB...`.
+/// Quotes and whitespace left by TXT record splitting are ignored.
+pub fn rsa_modulus_from_dns_record(record: &str) -> Result<Vec<u8>, DkimError> {
+    let record: String = record
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '"')
+        .collect();
+    let mut public_key = None;
+    for tag in record.split(';') {
+        match tag.split_once('=') {
+            Some(("k", key_type)) if key_type != "rsa" => {
+                return Err(DkimError::InvalidRecord("not an RSA key"));
+            }
+            Some(("p", value)) => public_key = Some(value),
+            _ => {}
+        }
+    }
+    match public_key {
+        None => Err(DkimError::InvalidRecord("missing p= tag")),
+        Some("") => Err(DkimError::InvalidRecord("revoked key")),
+        Some(public_key) => rsa_modulus_from_der(&STANDARD.decode(public_key)?),
+    }
+}
+
+/// First INTEGER of `RSAPublicKey`, without the sign byte.
+fn read_modulus(rsa_key: &[u8]) -> Result<Vec<u8>, DkimError> {
+    let (modulus, _) = read_tlv(rsa_key, 0x02)?;
+    match modulus {
+        [0, rest @ ..] => Ok(rest.to_vec()),
+        [first, ..] if first & 0x80 == 0 => Ok(modulus.to_vec()),
+        _ => Err(DkimError::InvalidDer("invalid modulus")),
+    }
+}
+
+/// Splits a DER element with the expected `tag` into its content and the remaining bytes.
+fn read_tlv(der: &[u8], tag: u8) -> Result<(&[u8], &[u8]), DkimError> {
+    match der.first() {
+        Some(t) if *t == tag => {}
+        _ => return Err(DkimError::InvalidDer("unexpected tag")),
+    }
+    let (len, header) = match der.get(1) {
+        Some(len) if len & 0x80 == 0 => (*len as usize, 2),
+        Some(len) if (1..=4).contains(&(len & 0x7f)) => {
+            let num_bytes = (len & 0x7f) as usize;
+            let bytes = der
+                .get(2..2 + num_bytes)
+                .ok_or(DkimError::InvalidDer("truncated length"))?;
+            let len = bytes.iter().fold(0usize, |len, b| (len << 8) | *b as usize);
+            (len, 2 + num_bytes)
+        }
+        _ => return Err(DkimError::InvalidDer("invalid length")),
+    };
+    let end = header
+        .checked_add(len)
+        .filter(|end| *end <= der.len())
+        .ok_or(DkimError::InvalidDer("truncated element"))?;
+    Ok((&der[header..end], &der[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poseidon_fields;
+
+    // 2048-bit key generated with `openssl genrsa`, its modulus from `openssl rsa -modulus`.
+    const SPKI_PEM: &str = "\
+-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEA27RBnfbnfEppPXUmwiuF
+KNgb7FjF7b25TWlhLMY1T/8gaEMrDhvzR9ag99qAtOa3BhpkMR83qt+7FXD8YI2c
+0dtTVqttNulDkgS54x0wVMdqBOx0t+hrsyHvO55lmmzZVgN/YNWogaUoo/DdW2zf
+vEBkb9ctfoQ6I27wtWGKoyF1GUZ0qyHRAYA05NkgFdogS/Alz5Y4XZF5IzmBpAby
+flaCoJqAPbT07+RlEdfoftmhsUlxnoAVgflSbKCGy01EMUbyK4LngEETt5BjEOpg
+suB1G1693cmyd6nCZKzwZZ0iY/inkef+f+hrH1Iyjs0GjgNrPAfyV/B6Xc+1DfnB
+lwIDAQAB
+-----END PUBLIC KEY-----";
+
+    const PKCS1_PEM: &str = "\
+-----BEGIN RSA PUBLIC KEY-----
+MIIBCgKCAQEA27RBnfbnfEppPXUmwiuFKNgb7FjF7b25TWlhLMY1T/8gaEMrDhvz
+R9ag99qAtOa3BhpkMR83qt+7FXD8YI2c0dtTVqttNulDkgS54x0wVMdqBOx0t+hr
+syHvO55lmmzZVgN/YNWogaUoo/DdW2zfvEBkb9ctfoQ6I27wtWGKoyF1GUZ0qyHR
+AYA05NkgFdogS/Alz5Y4XZF5IzmBpAbyflaCoJqAPbT07+RlEdfoftmhsUlxnoAV
+gflSbKCGy01EMUbyK4LngEETt5BjEOpgsuB1G1693cmyd6nCZKzwZZ0iY/inkef+
+f+hrH1Iyjs0GjgNrPAfyV/B6Xc+1DfnBlwIDAQAB
+-----END RSA PUBLIC KEY-----";
+
+    const MODULUS: &str = "\
+dbb4419df6e77c4a693d7526c22b8528d81bec58c5edbdb94d69612cc6354fff\
+2068432b0e1bf347d6a0f7da80b4e6b7061a64311f37aadfbb1570fc608d9cd1\
+db5356ab6d36e9439204b9e31d3054c76a04ec74b7e86bb321ef3b9e659a6cd9\
+56037f60d5a881a528a3f0dd5b6cdfbc40646fd72d7e843a236ef0b5618aa321\
+75194674ab21d1018034e4d92015da204bf025cf96385d9179233981a406f27e\
+5682a09a803db4f4efe46511d7e87ed9a1b149719e801581f9526ca086cb4d44\
+3146f22b82e7804113b7906310ea60b2e0751b5ebdddc9b277a9c264acf0659d\
+2263f8a791e7fe7fe86b1f52328ecd068e036b3c07f257f07a5dcfb50df9c197";
+
+    fn modulus() -> Vec<u8> {
+        (0..MODULUS.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&MODULUS[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_modulus() {
+        assert_eq!(rsa_modulus(SPKI_PEM).unwrap(), modulus());
+        assert_eq!(rsa_modulus(PKCS1_PEM).unwrap(), modulus());
+
+        let body: String = SPKI_PEM
+            .lines()
+            .filter(|l| !l.starts_with("-----"))
+            .collect();
+        let der = STANDARD.decode(&body).unwrap();
+        assert_eq!(rsa_modulus_from_der(&der).unwrap(), modulus());
+        rsa_modulus_from_der(&der[..der.len() - 1]).expect_err("Truncated DER");
+
+        let without_end = &SPKI_PEM[..SPKI_PEM.len() - "-----END PUBLIC KEY-----".len()];
+        for truncated in [
+            "",
+            "-----BEGIN PUBLIC KEY",
+            "-----BEGIN PUBLIC KEY-----",
+            "-----BEGIN RSA PUBLIC KEY-----\n",
+            without_end,
+        ] {
+            assert!(matches!(
+                rsa_modulus_from_pem(truncated),
+                Err(DkimError::InvalidPem(_))
+            ));
+        }
+
+        let record = format!("v=DKIM1; k=rsa; p={}", body);
+        assert_eq!(rsa_modulus(&record).unwrap(), modulus());
+        let split = format!(
+            "\"v=DKIM1; k=rsa; p={}\" \"{}\"",
+            &body[..200],
+            &body[200..]
+        );
+        assert_eq!(rsa_modulus(&split).unwrap(), modulus());
+        rsa_modulus("v=DKIM1; k=rsa; p=").expect_err("Revoked key");
+        rsa_modulus(&format!("v=DKIM1; k=ed25519; p={}", body)).expect_err("Not RSA");
+    }
+
+    #[test]
+    fn test_public_key_hash() {
+        let limbs = modulus_limbs(&modulus()).unwrap();
+        assert_eq!(limbs.len(), NUM_LIMBS);
+        let mut composed = BigUint::from(0u32);
+        for limb in limbs.iter().rev() {
+            let limb = BigUint::from_bytes_le(limb.to_repr().as_ref());
+            assert!(limb.bits() <= LIMB_BITS as u64);
+            composed = (composed << LIMB_BITS) + limb;
+        }
+        assert_eq!(composed.to_bytes_be(), modulus());
+
+        let shift = Fr::from_u128(1u128 << LIMB_BITS);
+        let mut inputs: Vec<Fr> = limbs
+            .chunks(2)
+            .map(|pair| pair[0] + pair.get(1).map_or(Fr::ZERO, |high| *high * shift))
+            .collect();
+        let hash = public_key_hash_from_key(SPKI_PEM).unwrap();
+        assert_eq!(inputs.len(), 9);
+        assert_eq!(hash, poseidon_fields(&inputs).unwrap());
+        inputs[8] += Fr::ONE;
+        assert_ne!(hash, poseidon_fields(&inputs).unwrap());
+
+        public_key_hash(&[0xff; 258]).expect_err("Modulus too large");
+    }
+}
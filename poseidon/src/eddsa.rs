@@ -0,0 +1,194 @@
+use crate::babyjubjub::{base8, sub_order, BabyJubjubError, Point};
+use crate::blake512::blake512;
+use crate::{poseidon_fields, Fr};
+use halo2curves::ff::*;
+use num_bigint::BigUint;
+
+/// EdDSA private key, hashed with BLAKE-512 into the secret scalar and the nonce seed
+/// as circomlibjs `eddsa` does.
+/// Key derivation and signing use the variable-time [`Point::mul_scalar`] and `BigUint`
+/// arithmetic, so they leak timing information about the key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrivateKey {
+    key: Vec<u8>,
+}
+
+/// EdDSA-Poseidon signature, `(R8, S)` in circomlib.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub r_b8: Point,
+    pub s: BigUint,
+}
+
+impl PrivateKey {
+    pub fn new(key: [u8; 32]) -> PrivateKey {
+        PrivateKey { key: key.to_vec() }
+    }
+
+    /// Key of any length, as zk-kit `eddsa-poseidon` accepts: the whole key is hashed, and a
+    /// string key is taken as its UTF-8 bytes.
+    pub fn from_bytes(key: &[u8]) -> PrivateKey {
+        PrivateKey { key: key.to_vec() }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Secret scalar, the pruned first half of the BLAKE-512 digest shifted right by 3
+    /// (`deriveSecretScalar` in zk-kit), so that `public() = Base8 * scalar_key()`.
+    pub fn scalar_key(&self) -> BigUint {
+        pruned_scalar(&blake512(&self.key)) >> 3
+    }
+
+    pub fn public(&self) -> Point {
+        base8().mul_scalar(&self.scalar_key())
+    }
+
+    /// circomlibjs `signPoseidon`.
+    pub fn sign(&self, message: &Fr) -> Signature {
+        let digest = blake512(&self.key);
+        let s = pruned_scalar(&digest);
+        let public = base8().mul_scalar(&(&s >> 3));
+
+        let mut nonce_input = digest[32..].to_vec();
+        nonce_input.extend_from_slice(message.to_repr().as_ref());
+        let r = BigUint::from_bytes_le(&blake512(&nonce_input)) % sub_order();
+        let r_b8 = base8().mul_scalar(&r);
+
+        let hm = challenge(&r_b8, &public, message);
+        let s = (r + hm * s) % sub_order();
+        Signature { r_b8, s }
+    }
+}
+
+impl Signature {
+    /// circomlibjs `packSignature`: the compressed `R8` followed by `S` in little-endian.
+    /// `S` must be below the subgroup order, as in every valid signature.
+    pub fn compress(&self) -> Result<[u8; 64], BabyJubjubError> {
+        if &self.s >= sub_order() {
+            return Err(BabyJubjubError::ScalarOutOfRange);
+        }
+        let mut bytes = [0u8; 64];
+        bytes[..32].copy_from_slice(&self.r_b8.compress());
+        let s = self.s.to_bytes_le();
+        bytes[32..32 + s.len()].copy_from_slice(&s);
+        Ok(bytes)
+    }
+
+    pub fn decompress(bytes: &[u8; 64]) -> Result<Signature, BabyJubjubError> {
+        let mut r_b8 = [0u8; 32];
+        r_b8.copy_from_slice(&bytes[..32]);
+        Ok(Signature {
+            r_b8: Point::decompress(&r_b8)?,
+            s: BigUint::from_bytes_le(&bytes[32..]),
+        })
+    }
+}
+
+/// circomlibjs `verifyPoseidon`: checks `Base8 * S = R8 + (8 * H(R8, A, message)) * A`.
+pub fn verify(public: &Point, signature: &Signature, message: &Fr) -> bool {
+    if !signature.r_b8.is_on_curve() || !public.is_on_curve() || &signature.s >= sub_order() {
+        return false;
+    }
+    let hm = challenge(&signature.r_b8, public, message);
+    let left = base8().mul_scalar(&signature.s);
+    let right = signature
+        .r_b8
+        .add(&public.mul_scalar(&(hm * BigUint::from(8u32))));
+    left == right
+}
+
+/// `Poseidon(R8.x, R8.y, A.x, A.y, message)` as an integer.
+fn challenge(r_b8: &Point, public: &Point, message: &Fr) -> BigUint {
+    let hm = poseidon_fields(&[r_b8.x(), r_b8.y(), public.x(), public.y(), *message]).unwrap();
+    BigUint::from_bytes_le(hm.to_repr().as_ref())
+}
+
+/// First half of the digest with the lowest 3 bits cleared and bit 254 set, as a little-endian integer.
+fn pruned_scalar(digest: &[u8; 64]) -> BigUint {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&digest[..32]);
+    bytes[0] &= 0xf8;
+    bytes[31] &= 0x7f;
+    bytes[31] |= 0x40;
+    BigUint::from_bytes_le(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fr(value: &str) -> Fr {
+        Fr::from_str_vartime(value).unwrap()
+    }
+
+    fn private_key() -> PrivateKey {
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = (i % 10) as u8;
+        }
+        key[31] = 1;
+        PrivateKey::new(key)
+    }
+
+    // Test vectors of circomlibjs `eddsa` tests.
+    #[test]
+    fn test_circomlibjs_vectors() {
+        let private_key = private_key();
+        let public = private_key.public();
+        assert_eq!(
+            public.x(),
+            fr("13277427435165878497778222415993513565335242147425444199013288855685581939618")
+        );
+        assert_eq!(
+            public.y(),
+            fr("13622229784656158136036771217484571176836296686641868549125388198837476602820")
+        );
+
+        let message = Fr::from_u128(0x09080706050403020100);
+        let signature = private_key.sign(&message);
+        assert_eq!(
+            signature.r_b8.x(),
+            fr("11384336176656855268977457483345535180380036354188103142384839473266348197733")
+        );
+        assert_eq!(
+            signature.r_b8.y(),
+            fr("15383486972088797283337779941324724402501462225528836549661220478783371668959")
+        );
+        assert_eq!(
+            signature.s,
+            BigUint::parse_bytes(
+                b"1672775540645840396591609181675628451599263765380031905495115170613215233181",
+                10
+            )
+            .unwrap()
+        );
+        assert!(verify(&public, &signature, &message));
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let private_key = private_key();
+        let public = private_key.public();
+        let message = Fr::from(42);
+        let signature = private_key.sign(&message);
+        assert!(verify(&public, &signature, &message));
+        assert!(!verify(&public, &signature, &Fr::from(43)));
+        assert!(!verify(
+            &PrivateKey::new([7; 32]).public(),
+            &signature,
+            &message
+        ));
+
+        let decompressed = Signature::decompress(&signature.compress().unwrap()).unwrap();
+        assert_eq!(decompressed, signature);
+
+        let mut tampered = signature.clone();
+        tampered.s += sub_order();
+        assert!(!verify(&public, &tampered, &message));
+        assert_eq!(tampered.compress(), Err(BabyJubjubError::ScalarOutOfRange));
+        tampered.s = BigUint::from(1u32) << 256;
+        assert_eq!(tampered.compress(), Err(BabyJubjubError::ScalarOutOfRange));
+    }
+}
@@ -0,0 +1,266 @@
+use crate::utils::bytes_to_fields;
+use crate::{compose_fields, poseidon_fields, Fr};
+use halo2curves::ff::*;
+
+/// Number of encoding elements absorbed per Poseidon call in [`hash_object`],
+/// the running state taking the 16th input.
+pub const HASH_OBJECT_CHUNK: usize = 15;
+
+/// Deterministic encoding of a value as field elements.
+///
+/// Fixed-size types (integers, `bool`, `Fr`, arrays and tuples of fixed-size types) encode
+/// to a fixed number of elements, and variable-size types (`str`, slices, `Vec`) are prefixed
+/// with their length, so that concatenated encodings stay unambiguous.
+/// Bytes are packed 31 per element, so `u8` is only encodable inside byte containers.
+pub trait ToFieldElements {
+    /// Appends the encoding of `self` to `out`.
+    fn append_fields(&self, out: &mut Vec<Fr>);
+
+    fn to_fields(&self) -> Vec<Fr> {
+        let mut out = Vec::new();
+        self.append_fields(&mut out);
+        out
+    }
+}
+
+/// Values hashed with [`hash_object`], implemented for every [`ToFieldElements`] type.
+pub trait PoseidonHashable: ToFieldElements {
+    fn poseidon_hash(&self) -> Fr {
+        hash_object(self)
+    }
+}
+
+impl<T: ToFieldElements + ?Sized> PoseidonHashable for T {}
+
+/// Hashes the encoding of `value` in chunks of [`HASH_OBJECT_CHUNK`] elements, chaining
+/// from the encoding length: `h = len`, then `h = Poseidon(h, chunk...)` for each chunk.
+/// Encodings of up to 15 elements take a single Poseidon call.
+pub fn hash_object<T: ToFieldElements + ?Sized>(value: &T) -> Fr {
+    hash_encoding(&value.to_fields())
+}
+
+/// [`hash_object`] over an already computed encoding.
+pub(crate) fn hash_encoding(fields: &[Fr]) -> Fr {
+    let mut state = Fr::from(fields.len() as u64);
+    let mut inputs = Vec::with_capacity(HASH_OBJECT_CHUNK + 1);
+    for chunk in fields.chunks(HASH_OBJECT_CHUNK) {
+        inputs.clear();
+        inputs.push(state);
+        inputs.extend_from_slice(chunk);
+        state = poseidon_fields(&inputs).unwrap();
+    }
+    if fields.is_empty() {
+        state = poseidon_fields(&[state]).unwrap();
+    }
+    state
+}
+
+/// Appends the byte length, then `bytes` composed `width` bytes per element, little-endian.
+/// Used by `#[poseidon(pack_bytes = width)]` fields of `#[derive(PoseidonHash)]`.
+pub fn append_packed_bytes(bytes: &[u8], width: usize, out: &mut Vec<Fr>) {
+    assert!(
+        (1..=31).contains(&width),
+        "Packing width must be 1 to 31 bytes"
+    );
+    let bytes: Vec<Fr> = bytes.iter().map(|b| Fr::from(*b as u64)).collect();
+    out.push(Fr::from(bytes.len() as u64));
+    out.extend(compose_fields(&bytes, width, 8));
+}
+
+impl ToFieldElements for Fr {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        out.push(*self);
+    }
+}
+
+impl ToFieldElements for bool {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        out.push(Fr::from(*self as u64));
+    }
+}
+
+macro_rules! impl_unsigned {
+    ($($t:ty),*) => {
+        $(impl ToFieldElements for $t {
+            fn append_fields(&self, out: &mut Vec<Fr>) {
+                out.push(Fr::from_u128(*self as u128));
+            }
+        })*
+    };
+}
+
+impl_unsigned!(u16, u32, u64, u128, usize);
+
+/// Negative integers map to the field negation of their absolute value.
+macro_rules! impl_signed {
+    ($($t:ty),*) => {
+        $(impl ToFieldElements for $t {
+            fn append_fields(&self, out: &mut Vec<Fr>) {
+                let abs = Fr::from_u128(self.unsigned_abs() as u128);
+                out.push(if *self < 0 { -abs } else { abs });
+            }
+        })*
+    };
+}
+
+impl_signed!(i8, i16, i32, i64, i128, isize);
+
+impl<const N: usize> ToFieldElements for [u8; N] {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        out.extend(bytes_to_fields(self));
+    }
+}
+
+impl ToFieldElements for [u8] {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        out.push(Fr::from(self.len() as u64));
+        out.extend(bytes_to_fields(self));
+    }
+}
+
+impl ToFieldElements for Vec<u8> {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        self.as_slice().append_fields(out);
+    }
+}
+
+impl ToFieldElements for str {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        self.as_bytes().append_fields(out);
+    }
+}
+
+impl ToFieldElements for String {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        self.as_bytes().append_fields(out);
+    }
+}
+
+impl<T: ToFieldElements, const N: usize> ToFieldElements for [T; N] {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        for item in self.iter() {
+            item.append_fields(out);
+        }
+    }
+}
+
+impl<T: ToFieldElements> ToFieldElements for [T] {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        out.push(Fr::from(self.len() as u64));
+        for item in self.iter() {
+            item.append_fields(out);
+        }
+    }
+}
+
+impl<T: ToFieldElements> ToFieldElements for Vec<T> {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        self.as_slice().append_fields(out);
+    }
+}
+
+impl<T: ToFieldElements + ?Sized> ToFieldElements for &T {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        (**self).append_fields(out);
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: ToFieldElements),+> ToFieldElements for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn append_fields(&self, out: &mut Vec<Fr>) {
+                let ($($name,)+) = self;
+                $($name.append_fields(out);)+
+            }
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings() {
+        assert_eq!(true.to_fields(), vec![Fr::ONE]);
+        assert_eq!((-3i32).to_fields(), vec![-Fr::from(3)]);
+        assert_eq!(i64::MIN.to_fields(), vec![-Fr::from_u128(1 << 63)]);
+        assert_eq!(u128::MAX.to_fields(), vec![Fr::from_u128(u128::MAX)]);
+        assert_eq!([1u8, 2].to_fields(), vec![Fr::from(0x0201)]);
+        assert_eq!("ab".to_fields(), vec![Fr::from(2), Fr::from(0x6261)]);
+        assert_eq!(vec![5u8; 32].to_fields().len(), 3);
+        assert_eq!(
+            (7u64, vec![1u32, 2], [Fr::ONE; 2]).to_fields(),
+            vec![
+                Fr::from(7),
+                Fr::from(2),
+                Fr::from(1),
+                Fr::from(2),
+                Fr::ONE,
+                Fr::ONE
+            ]
+        );
+    }
+
+    #[test]
+    fn test_packed_bytes() {
+        let mut out = Vec::new();
+        append_packed_bytes(b"abcde", 2, &mut out);
+        assert_eq!(
+            out,
+            vec![
+                Fr::from(5),
+                Fr::from(0x6261),
+                Fr::from(0x6463),
+                Fr::from(0x65)
+            ]
+        );
+        let mut packed = Vec::new();
+        append_packed_bytes(&[7; 40], 31, &mut packed);
+        assert_eq!(packed, vec![7u8; 40].to_fields());
+    }
+
+    #[test]
+    fn test_length_safety() {
+        assert_ne!(hash_object(&("ab", "c")), hash_object(&("a", "bc")));
+        assert_ne!(hash_object(&vec![1u64]), hash_object(&vec![1u64, 0]));
+        assert_ne!(hash_object("a"), hash_object("a\0"));
+        assert_ne!(
+            hash_object(&(vec![1u64], vec![2u64, 3])),
+            hash_object(&(vec![1u64, 2], vec![3u64]))
+        );
+        assert_eq!(hash_object("abc"), "abc".to_string().poseidon_hash());
+    }
+
+    #[test]
+    fn test_hash_object_chaining() {
+        let short = [Fr::from(1), Fr::from(2)];
+        assert_eq!(
+            hash_object(&short),
+            poseidon_fields(&[Fr::from(2), Fr::from(1), Fr::from(2)]).unwrap()
+        );
+        assert_eq!(
+            hash_object(&Vec::<u64>::new()),
+            poseidon_fields(&[Fr::ONE, Fr::ZERO]).unwrap()
+        );
+        assert_eq!(
+            hash_object(&[Fr::ZERO; 0]),
+            poseidon_fields(&[Fr::ZERO]).unwrap()
+        );
+
+        let long: Vec<Fr> = (0..20).map(Fr::from).collect();
+        let fields = long.as_slice().to_fields();
+        assert_eq!(fields.len(), 21);
+        let first = poseidon_fields(&[&[Fr::from(21)], &fields[..15]].concat()).unwrap();
+        let second = poseidon_fields(&[&[first], &fields[15..]].concat()).unwrap();
+        assert_eq!(hash_object(&long), second);
+    }
+}
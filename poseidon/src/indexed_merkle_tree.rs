@@ -0,0 +1,457 @@
+use crate::merkle_tree::{MerkleProof, MerkleTree, MerkleTreeError};
+use crate::storage::{MemoryStore, NodeKey, NodeStore, StorageError, METADATA_LEVEL};
+use crate::utils::{fr_to_be_bytes, fr_to_u64};
+use crate::{poseidon_fields, poseidon_fields_batch_fixed, Fr, PoseidonError};
+use halo2curves::ff::*;
+use std::collections::BTreeMap;
+
+/// Leaf of an indexed Merkle tree. The leaves form a linked list sorted by value,
+/// where `next_value == 0` marks the leaf holding the largest value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexedLeaf {
+    pub value: Fr,
+    pub next_index: u64,
+    pub next_value: Fr,
+}
+
+impl IndexedLeaf {
+    /// `Poseidon(value, next_index, next_value)`, using the t=4 parameters.
+    pub fn hash(&self) -> Result<Fr, PoseidonError> {
+        poseidon_fields(&[self.value, Fr::from(self.next_index), self.next_value])
+    }
+
+    /// Whether `value` falls strictly between this leaf and its successor.
+    pub fn is_low_leaf_of(&self, value: &Fr) -> bool {
+        let value = fr_to_be_bytes(value);
+        fr_to_be_bytes(&self.value) < value
+            && (self.next_value == Fr::ZERO || value < fr_to_be_bytes(&self.next_value))
+    }
+}
+
+/// Proof that a value is not in the tree: the low leaf whose range covers the value,
+/// and its inclusion proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonMembershipProof {
+    pub low_leaf: IndexedLeaf,
+    pub low_leaf_proof: MerkleProof,
+}
+
+impl NonMembershipProof {
+    /// Verifies the proof against the `root` of a tree of `depth`.
+    pub fn verify(&self, depth: usize, root: Fr, value: Fr) -> Result<bool, MerkleTreeError> {
+        Ok(self.low_leaf.is_low_leaf_of(&value)
+            && self
+                .low_leaf_proof
+                .verify(depth, root, self.low_leaf.hash()?)?)
+    }
+}
+
+/// Witness of a single insertion, as consumed by an insertion circuit:
+/// the low leaf is updated first, then the new leaf is written into an empty slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsertionWitness {
+    pub old_root: Fr,
+    /// Low leaf before the update, and its proof against `old_root`.
+    pub low_leaf: IndexedLeaf,
+    pub low_leaf_proof: MerkleProof,
+    /// New leaf, and its proof against the root after the low leaf update.
+    pub new_leaf: IndexedLeaf,
+    pub new_leaf_proof: MerkleProof,
+    pub new_root: Fr,
+}
+
+impl InsertionWitness {
+    /// Verifies the witness of an insertion into a tree of `depth`.
+    pub fn verify(&self, depth: usize) -> Result<bool, MerkleTreeError> {
+        if !self.low_leaf.is_low_leaf_of(&self.new_leaf.value)
+            || !self
+                .low_leaf_proof
+                .verify(depth, self.old_root, self.low_leaf.hash()?)?
+        {
+            return Ok(false);
+        }
+        if self.new_leaf.next_index != self.low_leaf.next_index
+            || self.new_leaf.next_value != self.low_leaf.next_value
+        {
+            return Ok(false);
+        }
+        let updated_low_leaf = IndexedLeaf {
+            value: self.low_leaf.value,
+            next_index: self.new_leaf_proof.leaf_index,
+            next_value: self.new_leaf.value,
+        };
+        let intermediate_root = self
+            .low_leaf_proof
+            .compute_root(depth, updated_low_leaf.hash()?)?;
+        Ok(self
+            .new_leaf_proof
+            .verify(depth, intermediate_root, Fr::ZERO)?
+            && self
+                .new_leaf_proof
+                .verify(depth, self.new_root, self.new_leaf.hash()?)?)
+    }
+}
+
+const LEAF_COUNT_KEY: NodeKey = (METADATA_LEVEL, 1);
+const LEAF_VALUE_LEVEL: usize = METADATA_LEVEL - 1;
+const LEAF_NEXT_INDEX_LEVEL: usize = METADATA_LEVEL - 2;
+const LEAF_NEXT_VALUE_LEVEL: usize = METADATA_LEVEL - 3;
+
+/// Indexed Merkle tree, as used by Aztec for nullifier sets.
+/// The leaf at index 0 is the zero leaf `(0, 0, 0)`, so zero itself can never be inserted.
+/// Leaf preimages are kept in the store next to the nodes; only the sorted value index lives in memory.
+#[derive(Debug, Clone)]
+pub struct IndexedMerkleTree<S: NodeStore = MemoryStore> {
+    tree: MerkleTree<S>,
+    len: u64,
+    sorted: BTreeMap<[u8; 32], u64>,
+}
+
+impl IndexedMerkleTree<MemoryStore> {
+    pub fn new(depth: usize) -> Result<IndexedMerkleTree, MerkleTreeError> {
+        IndexedMerkleTree::with_store(depth, MemoryStore::new())
+    }
+
+    /// Builds a tree holding `values` at indices `1..`, in the given order.
+    pub fn from_values(depth: usize, values: &[Fr]) -> Result<IndexedMerkleTree, MerkleTreeError> {
+        IndexedMerkleTree::from_values_with_store(depth, values, MemoryStore::new())
+    }
+}
+
+impl<S: NodeStore> IndexedMerkleTree<S> {
+    /// Opens a tree on top of `store`, resuming from the leaves it already holds.
+    pub fn with_store(depth: usize, store: S) -> Result<IndexedMerkleTree<S>, MerkleTreeError> {
+        let mut tree = IndexedMerkleTree {
+            tree: MerkleTree::with_store(depth, store)?,
+            len: 0,
+            sorted: BTreeMap::new(),
+        };
+        let len = match tree.tree.store().get(LEAF_COUNT_KEY)? {
+            Some(len) => fr_to_u64(&len).ok_or_else(|| {
+                StorageError::Corrupted("leaf count does not fit in u64".to_string())
+            })?,
+            None => 0,
+        };
+        if len == 0 {
+            let zero_leaf = IndexedLeaf {
+                value: Fr::ZERO,
+                next_index: 0,
+                next_value: Fr::ZERO,
+            };
+            tree.write_leaf(0, &zero_leaf)?;
+            tree.tree.update(0, zero_leaf.hash()?)?;
+            tree.set_len(1)?;
+        } else {
+            tree.len = len;
+        }
+        for index in 0..tree.len {
+            let value = tree
+                .tree
+                .store()
+                .get((LEAF_VALUE_LEVEL, index))?
+                .ok_or_else(|| StorageError::Corrupted(format!("missing leaf {}", index)))?;
+            tree.sorted.insert(fr_to_be_bytes(&value), index);
+        }
+        Ok(tree)
+    }
+
+    /// Builds a tree on top of an empty `store` holding `values` at indices `1..`, in the given order.
+    /// Leaves are hashed and the tree is built in bulk, in parallel with the `parallel` feature.
+    /// Fails with [`MerkleTreeError::StoreNotEmpty`] if `store` already holds nodes.
+    pub fn from_values_with_store(
+        depth: usize,
+        values: &[Fr],
+        store: S,
+    ) -> Result<IndexedMerkleTree<S>, MerkleTreeError> {
+        if !store.is_empty() {
+            return Err(MerkleTreeError::StoreNotEmpty);
+        }
+        let mut sorted = BTreeMap::new();
+        sorted.insert(fr_to_be_bytes(&Fr::ZERO), 0);
+        for (i, value) in values.iter().enumerate() {
+            if sorted.insert(fr_to_be_bytes(value), i as u64 + 1).is_some() {
+                return Err(MerkleTreeError::DuplicateValue);
+            }
+        }
+
+        let mut leaves = vec![
+            IndexedLeaf {
+                value: Fr::ZERO,
+                next_index: 0,
+                next_value: Fr::ZERO,
+            };
+            values.len() + 1
+        ];
+        for (i, value) in values.iter().enumerate() {
+            leaves[i + 1].value = *value;
+        }
+        let order: Vec<u64> = sorted.values().copied().collect();
+        for pair in order.windows(2) {
+            let next_value = leaves[pair[1] as usize].value;
+            let leaf = &mut leaves[pair[0] as usize];
+            leaf.next_index = pair[1];
+            leaf.next_value = next_value;
+        }
+
+        let preimages: Vec<[Fr; 3]> = leaves
+            .iter()
+            .map(|leaf| [leaf.value, Fr::from(leaf.next_index), leaf.next_value])
+            .collect();
+        let hashes = poseidon_fields_batch_fixed(&preimages)?;
+        let mut tree = IndexedMerkleTree {
+            tree: MerkleTree::from_leaves_with_store(depth, &hashes, store)?,
+            len: 0,
+            sorted,
+        };
+        for (index, leaf) in leaves.iter().enumerate() {
+            tree.write_leaf(index as u64, leaf)?;
+        }
+        tree.set_len(leaves.len() as u64)?;
+        Ok(tree)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.tree.depth()
+    }
+
+    pub fn root(&self) -> Fr {
+        self.tree.root()
+    }
+
+    /// Number of leaves, including the zero leaf.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 1
+    }
+
+    pub fn leaf(&self, index: u64) -> Result<Option<IndexedLeaf>, MerkleTreeError> {
+        if index >= self.len {
+            return Ok(None);
+        }
+        let store = self.tree.store();
+        let read = |level: usize| {
+            store
+                .get((level, index))?
+                .ok_or_else(|| StorageError::Corrupted(format!("missing leaf {}", index)))
+        };
+        let next_index = read(LEAF_NEXT_INDEX_LEVEL)?;
+        Ok(Some(IndexedLeaf {
+            value: read(LEAF_VALUE_LEVEL)?,
+            next_index: fr_to_u64(&next_index).ok_or_else(|| {
+                StorageError::Corrupted(format!("invalid next index of leaf {}", index))
+            })?,
+            next_value: read(LEAF_NEXT_VALUE_LEVEL)?,
+        }))
+    }
+
+    pub fn index_of(&self, value: &Fr) -> Option<u64> {
+        self.sorted.get(&fr_to_be_bytes(value)).copied()
+    }
+
+    pub fn contains(&self, value: &Fr) -> bool {
+        self.index_of(value).is_some()
+    }
+
+    pub fn proof(&self, index: u64) -> Result<MerkleProof, MerkleTreeError> {
+        self.tree.proof(index)
+    }
+
+    /// Index of the leaf with the largest value smaller than `value`.
+    pub fn low_leaf_index(&self, value: &Fr) -> Result<u64, MerkleTreeError> {
+        let key = fr_to_be_bytes(value);
+        if self.sorted.contains_key(&key) {
+            return Err(MerkleTreeError::DuplicateValue);
+        }
+        let (_, index) = self
+            .sorted
+            .range(..key)
+            .next_back()
+            .expect("the zero leaf is smaller than any other value");
+        Ok(*index)
+    }
+
+    pub fn non_membership_proof(&self, value: &Fr) -> Result<NonMembershipProof, MerkleTreeError> {
+        let low_index = self.low_leaf_index(value)?;
+        Ok(NonMembershipProof {
+            low_leaf: self.existing_leaf(low_index)?,
+            low_leaf_proof: self.tree.proof(low_index)?,
+        })
+    }
+
+    pub fn insert(&mut self, value: Fr) -> Result<InsertionWitness, MerkleTreeError> {
+        let low_index = self.low_leaf_index(&value)?;
+        let new_index = self.len;
+        if new_index >= self.tree.capacity() {
+            return Err(MerkleTreeError::TreeFull(self.tree.capacity()));
+        }
+        let old_root = self.root();
+        let low_leaf = self.existing_leaf(low_index)?;
+        let low_leaf_proof = self.tree.proof(low_index)?;
+
+        let new_leaf = IndexedLeaf {
+            value,
+            next_index: low_leaf.next_index,
+            next_value: low_leaf.next_value,
+        };
+        let updated_low_leaf = IndexedLeaf {
+            value: low_leaf.value,
+            next_index: new_index,
+            next_value: value,
+        };
+        self.tree.update(low_index, updated_low_leaf.hash()?)?;
+        self.write_leaf(low_index, &updated_low_leaf)?;
+
+        let new_leaf_proof = self.tree.proof(new_index)?;
+        self.tree.update(new_index, new_leaf.hash()?)?;
+        self.write_leaf(new_index, &new_leaf)?;
+        self.set_len(new_index + 1)?;
+        self.sorted.insert(fr_to_be_bytes(&value), new_index);
+
+        Ok(InsertionWitness {
+            old_root,
+            low_leaf,
+            low_leaf_proof,
+            new_leaf,
+            new_leaf_proof,
+            new_root: self.root(),
+        })
+    }
+
+    pub fn flush(&mut self) -> Result<(), MerkleTreeError> {
+        self.tree.flush()
+    }
+
+    pub fn store(&self) -> &S {
+        self.tree.store()
+    }
+
+    pub fn into_store(self) -> S {
+        self.tree.into_store()
+    }
+
+    fn existing_leaf(&self, index: u64) -> Result<IndexedLeaf, MerkleTreeError> {
+        Ok(self
+            .leaf(index)?
+            .expect("indices in the sorted index exist"))
+    }
+
+    fn write_leaf(&mut self, index: u64, leaf: &IndexedLeaf) -> Result<(), MerkleTreeError> {
+        let store = self.tree.store_mut();
+        store.put((LEAF_VALUE_LEVEL, index), leaf.value)?;
+        store.put((LEAF_NEXT_INDEX_LEVEL, index), Fr::from(leaf.next_index))?;
+        store.put((LEAF_NEXT_VALUE_LEVEL, index), leaf.next_value)?;
+        Ok(())
+    }
+
+    fn set_len(&mut self, len: u64) -> Result<(), MerkleTreeError> {
+        self.tree.store_mut().put(LEAF_COUNT_KEY, Fr::from(len))?;
+        self.len = len;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_keeps_sorted_list() {
+        let mut tree = IndexedMerkleTree::new(4).unwrap();
+        for value in [30u64, 10, 20] {
+            let witness = tree.insert(Fr::from(value)).unwrap();
+            assert!(witness.verify(4).unwrap());
+            assert!(witness.verify(3).is_err());
+            assert_eq!(witness.new_root, tree.root());
+        }
+        assert_eq!(tree.len(), 4);
+
+        let mut index = 0;
+        let mut values = Vec::new();
+        loop {
+            let leaf = tree.leaf(index).unwrap().unwrap();
+            if leaf.next_value == Fr::ZERO {
+                break;
+            }
+            values.push(leaf.next_value);
+            index = leaf.next_index;
+        }
+        assert_eq!(values, vec![Fr::from(10), Fr::from(20), Fr::from(30)]);
+
+        for i in 0..tree.len() {
+            let proof = tree.proof(i).unwrap();
+            let leaf_hash = tree.leaf(i).unwrap().unwrap().hash().unwrap();
+            assert!(proof.verify(4, tree.root(), leaf_hash).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_non_membership() {
+        let mut tree = IndexedMerkleTree::new(4).unwrap();
+        tree.insert(Fr::from(10)).unwrap();
+        tree.insert(Fr::from(20)).unwrap();
+
+        let proof = tree.non_membership_proof(&Fr::from(15)).unwrap();
+        assert_eq!(proof.low_leaf.value, Fr::from(10));
+        assert!(proof.verify(4, tree.root(), Fr::from(15)).unwrap());
+        assert!(!proof.verify(4, tree.root(), Fr::from(25)).unwrap());
+
+        let proof = tree.non_membership_proof(&Fr::from(25)).unwrap();
+        assert_eq!(proof.low_leaf.value, Fr::from(20));
+        assert!(proof.verify(4, tree.root(), Fr::from(25)).unwrap());
+        assert!(proof.verify(4, tree.root(), -Fr::ONE).unwrap());
+
+        tree.non_membership_proof(&Fr::from(20))
+            .expect_err("Value already exists");
+        tree.insert(Fr::from(20)).expect_err("Value already exists");
+        tree.insert(Fr::ZERO).expect_err("Value already exists");
+    }
+
+    #[test]
+    fn test_from_values() {
+        let values: Vec<Fr> = [30u64, 10, 50, 20].iter().map(|v| Fr::from(*v)).collect();
+        let tree = IndexedMerkleTree::from_values(4, &values).unwrap();
+        let mut expected = IndexedMerkleTree::new(4).unwrap();
+        for value in values.iter() {
+            expected.insert(*value).unwrap();
+        }
+        assert_eq!(tree.root(), expected.root());
+        assert_eq!(tree.len(), 5);
+        for i in 0..tree.len() {
+            assert_eq!(tree.leaf(i).unwrap(), expected.leaf(i).unwrap());
+        }
+
+        IndexedMerkleTree::from_values(4, &[Fr::from(1), Fr::from(1)])
+            .expect_err("Value already exists");
+        IndexedMerkleTree::from_values(4, &[Fr::ZERO]).expect_err("Value already exists");
+        assert!(matches!(
+            IndexedMerkleTree::from_values_with_store(4, &values, tree.into_store()),
+            Err(MerkleTreeError::StoreNotEmpty)
+        ));
+    }
+
+    #[test]
+    fn test_reopen_store() {
+        let mut tree = IndexedMerkleTree::new(8).unwrap();
+        tree.insert(Fr::from(10)).unwrap();
+        tree.insert(Fr::from(20)).unwrap();
+        let root = tree.root();
+
+        let mut tree = IndexedMerkleTree::with_store(8, tree.into_store()).unwrap();
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.len(), 3);
+        assert!(tree.contains(&Fr::from(20)));
+        let witness = tree.insert(Fr::from(15)).unwrap();
+        assert!(witness.verify(8).unwrap());
+        assert_eq!(witness.low_leaf.value, Fr::from(10));
+    }
+
+    #[test]
+    fn test_tree_full() {
+        let mut tree = IndexedMerkleTree::new(1).unwrap();
+        tree.insert(Fr::from(1)).unwrap();
+        tree.insert(Fr::from(2)).expect_err("Tree is full");
+    }
+}
@@ -0,0 +1,345 @@
+//! Canonical hashing of JSON documents for selective disclosure.
+//!
+//! A document is flattened into one leaf per scalar, keyed by its path from the root.
+//! Leaves are ordered by path (object keys bytewise, array elements by index), so key order
+//! in the source text does not matter, and each leaf is `Poseidon(H(path), H(value))` with
+//! `H` the [`hash_object`] of the [`ToFieldElements`] encodings below. The leaves are committed
+//! to in a [`MerkleTree`], and a single attribute is disclosed with its inclusion proof.
+//!
+//! Empty objects and arrays are leaves of their own so that they are committed to as well.
+//! The leaf index of a disclosure reveals the position of the attribute in the document.
+//!
+//! Numbers must be integers within `i64` or `u64`. Other numbers are rejected: serde_json
+//! parses them into an `f64`, so distinct numbers in the source text could share a leaf.
+
+use crate::hashable::{hash_object, ToFieldElements};
+use crate::merkle_tree::{MerkleProof, MerkleTree, MerkleTreeError};
+use crate::{poseidon_fields, Fr, PoseidonError};
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum JsonHashError {
+    #[error("No scalar at path `{0}`")]
+    UnknownPath(String),
+    #[error("Number `{0}` is not an integer within i64 or u64")]
+    UnsupportedNumber(String),
+    #[error(transparent)]
+    MerkleTree(#[from] MerkleTreeError),
+    #[error(transparent)]
+    Poseidon(#[from] PoseidonError),
+}
+
+/// Step of a path from the document root, encoded as a tag (`0` for keys, `1` for indices)
+/// followed by the key string or the index.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PathSegment {
+    Key(String),
+    Index(u64),
+}
+
+/// Typed scalar value of a leaf, encoded as its type tag followed by its content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonScalar {
+    Null,
+    Bool(bool),
+    Integer(i128),
+    String(String),
+    EmptyObject,
+    EmptyArray,
+}
+
+impl ToFieldElements for PathSegment {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        match self {
+            PathSegment::Key(key) => (0u64, key).append_fields(out),
+            PathSegment::Index(index) => (1u64, *index).append_fields(out),
+        }
+    }
+}
+
+impl ToFieldElements for JsonScalar {
+    fn append_fields(&self, out: &mut Vec<Fr>) {
+        match self {
+            JsonScalar::Null => 0u64.append_fields(out),
+            JsonScalar::Bool(value) => (1u64, *value).append_fields(out),
+            JsonScalar::Integer(value) => (2u64, *value).append_fields(out),
+            JsonScalar::String(value) => (3u64, value).append_fields(out),
+            JsonScalar::EmptyObject => 4u64.append_fields(out),
+            JsonScalar::EmptyArray => 5u64.append_fields(out),
+        }
+    }
+}
+
+impl JsonScalar {
+    /// `None` for non-empty objects and arrays, which are not leaves.
+    pub fn from_value(value: &Value) -> Result<Option<JsonScalar>, JsonHashError> {
+        Ok(Some(match value {
+            Value::Null => JsonScalar::Null,
+            Value::Bool(value) => JsonScalar::Bool(*value),
+            Value::Number(number) => match (number.as_i64(), number.as_u64()) {
+                (Some(value), _) => JsonScalar::Integer(value as i128),
+                (_, Some(value)) => JsonScalar::Integer(value as i128),
+                _ => return Err(JsonHashError::UnsupportedNumber(number.to_string())),
+            },
+            Value::String(value) => JsonScalar::String(value.clone()),
+            Value::Object(map) if map.is_empty() => JsonScalar::EmptyObject,
+            Value::Array(items) if items.is_empty() => JsonScalar::EmptyArray,
+            _ => return Ok(None),
+        }))
+    }
+}
+
+/// Path and value of a scalar in the document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonLeaf {
+    pub path: Vec<PathSegment>,
+    pub value: JsonScalar,
+}
+
+impl JsonLeaf {
+    /// `Poseidon(H(path), H(value))`.
+    pub fn hash(&self) -> Result<Fr, PoseidonError> {
+        poseidon_fields(&[hash_object(&self.path), hash_object(&self.value)])
+    }
+
+    /// RFC 6901 JSON Pointer of the path, e.g. `/credentialSubject/degree/name`.
+    pub fn pointer(&self) -> String {
+        let mut pointer = String::new();
+        for segment in self.path.iter() {
+            pointer.push('/');
+            match segment {
+                PathSegment::Key(key) => {
+                    pointer.push_str(&key.replace('~', "~0").replace('/', "~1"))
+                }
+                PathSegment::Index(index) => pointer.push_str(&index.to_string()),
+            }
+        }
+        pointer
+    }
+}
+
+/// Leaves of `document` in canonical order.
+pub fn canonical_leaves(document: &Value) -> Result<Vec<JsonLeaf>, JsonHashError> {
+    let mut leaves = Vec::new();
+    collect_leaves(document, &mut Vec::new(), &mut leaves)?;
+    Ok(leaves)
+}
+
+fn collect_leaves(
+    value: &Value,
+    path: &mut Vec<PathSegment>,
+    leaves: &mut Vec<JsonLeaf>,
+) -> Result<(), JsonHashError> {
+    if let Some(scalar) = JsonScalar::from_value(value)? {
+        leaves.push(JsonLeaf {
+            path: path.clone(),
+            value: scalar,
+        });
+        return Ok(());
+    }
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            for key in keys {
+                path.push(PathSegment::Key(key.clone()));
+                collect_leaves(&map[key.as_str()], path, leaves)?;
+                path.pop();
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                path.push(PathSegment::Index(index as u64));
+                collect_leaves(item, path, leaves)?;
+                path.pop();
+            }
+        }
+        _ => unreachable!("Scalars are leaves"),
+    }
+    Ok(())
+}
+
+/// Merkle commitment to the canonical leaves of a JSON document.
+#[derive(Debug, Clone)]
+pub struct JsonCommitment {
+    leaves: Vec<JsonLeaf>,
+    tree: MerkleTree,
+}
+
+/// Single attribute of a committed document with its inclusion proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disclosure {
+    pub leaf: JsonLeaf,
+    pub proof: MerkleProof,
+}
+
+impl JsonCommitment {
+    /// Commits to `document` in a tree of `depth`, which must fit all its leaves.
+    pub fn new(document: &Value, depth: usize) -> Result<JsonCommitment, JsonHashError> {
+        let leaves = canonical_leaves(document)?;
+        let hashes = leaves
+            .iter()
+            .map(JsonLeaf::hash)
+            .collect::<Result<Vec<Fr>, PoseidonError>>()?;
+        let tree = MerkleTree::from_leaves(depth, &hashes)?;
+        Ok(JsonCommitment { leaves, tree })
+    }
+
+    pub fn root(&self) -> Fr {
+        self.tree.root()
+    }
+
+    pub fn leaves(&self) -> &[JsonLeaf] {
+        &self.leaves
+    }
+
+    /// Discloses the scalar at the JSON Pointer `pointer`.
+    pub fn disclose(&self, pointer: &str) -> Result<Disclosure, JsonHashError> {
+        let index = self
+            .leaves
+            .iter()
+            .position(|leaf| leaf.pointer() == pointer)
+            .ok_or_else(|| JsonHashError::UnknownPath(pointer.to_string()))?;
+        Ok(Disclosure {
+            leaf: self.leaves[index].clone(),
+            proof: self.tree.proof(index as u64)?,
+        })
+    }
+}
+
+impl Disclosure {
+    /// Verifies the disclosure against the `root` of a commitment of `depth`.
+    pub fn verify(&self, depth: usize, root: Fr) -> Result<bool, JsonHashError> {
+        Ok(self.proof.verify(depth, root, self.leaf.hash()?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credential() -> Value {
+        serde_json::from_str(
+            r#"{
+                "issuer": "did:example:issuer",
+                "credentialSubject": {
+                    "name": "Alice",
+                    "age": 30,
+                    "degree": {"type": "BachelorDegree", "credits": 180},
+                    "tags": ["a/b", -2],
+                    "extra": {}
+                },
+                "revoked": false,
+                "note": null
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_canonical_leaves() {
+        let leaves = canonical_leaves(&credential()).unwrap();
+        let pointers: Vec<String> = leaves.iter().map(JsonLeaf::pointer).collect();
+        assert_eq!(
+            pointers,
+            vec![
+                "/credentialSubject/age",
+                "/credentialSubject/degree/credits",
+                "/credentialSubject/degree/type",
+                "/credentialSubject/extra",
+                "/credentialSubject/name",
+                "/credentialSubject/tags/0",
+                "/credentialSubject/tags/1",
+                "/issuer",
+                "/note",
+                "/revoked",
+            ]
+        );
+        assert_eq!(leaves[1].value, JsonScalar::Integer(180));
+        assert_eq!(leaves[3].value, JsonScalar::EmptyObject);
+        assert_eq!(leaves[6].value, JsonScalar::Integer(-2));
+        assert_eq!(
+            leaves[0].hash().unwrap(),
+            poseidon_fields(&[
+                hash_object(&vec![(0u64, "credentialSubject"), (0u64, "age")]),
+                hash_object(&(2u64, 30i128)),
+            ])
+            .unwrap()
+        );
+
+        let reordered: Value = serde_json::from_str(
+            r#"{"note": null, "revoked": false, "credentialSubject": {"extra": {}, "tags": ["a/b", -2],
+                "degree": {"credits": 180, "type": "BachelorDegree"}, "age": 30, "name": "Alice"},
+                "issuer": "did:example:issuer"}"#,
+        )
+        .unwrap();
+        assert_eq!(canonical_leaves(&reordered).unwrap(), leaves);
+    }
+
+    #[test]
+    fn test_typed_scalars() {
+        let hash = |json: &str| {
+            JsonCommitment::new(&serde_json::from_str(json).unwrap(), 2)
+                .unwrap()
+                .root()
+        };
+        assert_ne!(hash(r#"{"a": 1}"#), hash(r#"{"a": "1"}"#));
+        assert_ne!(hash(r#"{"a": 1}"#), hash(r#"{"a": true}"#));
+        assert_ne!(hash(r#"{"a": 0}"#), hash(r#"{"a": null}"#));
+        assert_ne!(hash(r#"{"a": {}}"#), hash(r#"{"a": []}"#));
+        assert_ne!(hash(r#"{"0": 1}"#), hash(r#"[1]"#));
+        assert_ne!(hash(r#"{"a": {"b": 1}}"#), hash(r#"{"a/b": 1}"#));
+        assert_ne!(
+            hash(r#"{"a": 18446744073709551615}"#),
+            hash(r#"{"a": -9223372036854775808}"#)
+        );
+    }
+
+    #[test]
+    fn test_unsupported_numbers() {
+        // The first two parse to the same f64, so they must not commit to a root at all.
+        for json in [
+            r#"{"a": 18446744073709551616}"#,
+            r#"{"a": 18446744073709551617}"#,
+            r#"{"a": -9223372036854775809}"#,
+            r#"{"a": 1.0}"#,
+            r#"{"a": [3.7]}"#,
+        ] {
+            assert!(matches!(
+                JsonCommitment::new(&serde_json::from_str(json).unwrap(), 2),
+                Err(JsonHashError::UnsupportedNumber(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_selective_disclosure() {
+        let commitment = JsonCommitment::new(&credential(), 4).unwrap();
+        let root = commitment.root();
+
+        let disclosure = commitment.disclose("/credentialSubject/age").unwrap();
+        assert_eq!(disclosure.leaf.value, JsonScalar::Integer(30));
+        assert!(disclosure.verify(4, root).unwrap());
+
+        let tag = commitment.disclose("/credentialSubject/tags/0").unwrap();
+        assert_eq!(tag.leaf.value, JsonScalar::String("a/b".to_string()));
+        assert!(tag.verify(4, root).unwrap());
+
+        let mut forged = disclosure.clone();
+        forged.leaf.value = JsonScalar::Integer(31);
+        assert!(!forged.verify(4, root).unwrap());
+        let mut moved = disclosure;
+        moved.leaf.path = tag.leaf.path;
+        assert!(!moved.verify(4, root).unwrap());
+
+        assert!(matches!(
+            commitment.disclose("/credentialSubject"),
+            Err(JsonHashError::UnknownPath(_))
+        ));
+        assert!(matches!(
+            JsonCommitment::new(&credential(), 3),
+            Err(JsonHashError::MerkleTree(MerkleTreeError::TreeFull(8)))
+        ));
+    }
+}
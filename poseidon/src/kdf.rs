@@ -0,0 +1,75 @@
+use crate::sponge::{domain_tag, PoseidonSponge};
+use crate::utils::bytes_to_fields;
+use crate::Fr;
+
+/// Domain of [`prf`], packed into the sponge capacity with [`domain_tag`].
+pub const PRF_DOMAIN: &[u8] = b"poseidon-rs/prf";
+/// Domain of [`kdf`], packed into the sponge capacity with [`domain_tag`].
+pub const KDF_DOMAIN: &[u8] = b"poseidon-rs/kdf";
+
+/// Rate of the PRF and KDF sponges, i.e. a width 3 permutation.
+const RATE: usize = 2;
+
+/// Keyed PRF: absorbs `key`, the input length and `input`, then squeezes one element.
+/// The length makes inputs differing only by trailing zeros map to distinct outputs.
+pub fn prf(key: &Fr, input: &[Fr]) -> Fr {
+    let mut sponge = PoseidonSponge::new(RATE, domain_tag(PRF_DOMAIN).unwrap()).unwrap();
+    sponge.absorb(key);
+    sponge.absorb(&Fr::from(input.len() as u64));
+    sponge.absorb_slice(input);
+    sponge.squeeze()
+}
+
+/// Expands `master` into `count` sub-keys for `label`: absorbs `master` and the label
+/// (its byte length, then 31 bytes per element), then squeezes `count` elements.
+/// The first keys do not depend on `count`.
+pub fn kdf(master: &Fr, label: &[u8], count: usize) -> Vec<Fr> {
+    let mut sponge = PoseidonSponge::new(RATE, domain_tag(KDF_DOMAIN).unwrap()).unwrap();
+    sponge.absorb(master);
+    sponge.absorb(&Fr::from(label.len() as u64));
+    sponge.absorb_slice(&bytes_to_fields(label));
+    (0..count).map(|_| sponge.squeeze()).collect()
+}
+
+/// Single sub-key for `label`, the first output of [`kdf`].
+pub fn derive_key(master: &Fr, label: &[u8]) -> Fr {
+    kdf(master, label, 1)[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poseidon_default;
+    use halo2curves::ff::*;
+
+    #[test]
+    fn test_prf() {
+        let key = Fr::from(7);
+        let output = prf(&key, &[Fr::from(1)]);
+        assert_eq!(output, prf(&key, &[Fr::from(1)]));
+        assert_ne!(output, prf(&Fr::from(8), &[Fr::from(1)]));
+        assert_ne!(output, prf(&key, &[Fr::from(1), Fr::ZERO]));
+        assert_ne!(output, prf(&key, &[Fr::from(2)]));
+
+        // `[tag, key, len]` fills the rate, the input starts the next block.
+        let mut state = vec![domain_tag(PRF_DOMAIN).unwrap(), key, Fr::ONE];
+        poseidon_default().permute(&mut state).unwrap();
+        state[1] += Fr::from(1);
+        poseidon_default().permute(&mut state).unwrap();
+        assert_eq!(output, state[1]);
+    }
+
+    #[test]
+    fn test_kdf() {
+        let master = Fr::from(1234);
+        let keys = kdf(&master, b"account/0", 3);
+        assert_eq!(keys.len(), 3);
+        assert_ne!(keys[0], keys[1]);
+        assert_eq!(kdf(&master, b"account/0", 1), keys[..1].to_vec());
+        assert_eq!(derive_key(&master, b"account/0"), keys[0]);
+        assert_ne!(derive_key(&master, b"account/1"), keys[0]);
+        assert_ne!(derive_key(&master, b"account/0\0"), keys[0]);
+        assert_ne!(derive_key(&Fr::from(1235), b"account/0"), keys[0]);
+        assert_ne!(derive_key(&master, b""), prf(&master, &[]));
+    }
+}
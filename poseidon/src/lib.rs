@@ -1,13 +1,52 @@
+pub mod babyjubjub;
+mod blake512;
+pub mod cipher;
+pub mod claim;
+pub mod commitment;
 pub mod constants;
+pub mod dkim;
+pub mod eddsa;
+pub mod hashable;
+pub mod indexed_merkle_tree;
+pub mod json_hash;
+pub mod kdf;
+pub mod merkle_tree;
+pub mod mmr;
 pub mod poseidon;
+pub mod rng;
+pub mod safe;
+pub mod semaphore;
+pub mod serde_fields;
+pub mod serde_fr;
+pub mod sponge;
+pub mod storage;
+pub mod trace;
+mod utils;
+pub mod zkemail;
 pub use halo2curves::bn256::Fr;
 use halo2curves::ff::*;
 use once_cell::sync::OnceCell;
 pub use poseidon::*;
+#[cfg(feature = "derive")]
+pub use poseidon_derive::PoseidonHash;
 
 pub fn poseidon_fields(input_fields: &[Fr]) -> Result<Fr, PoseidonError> {
     let poseidon = poseidon_default();
-    poseidon.hash(input_fields.to_vec())
+    poseidon.hash_slice(input_fields)
+}
+
+/// Hashes each input independently, across threads with the `parallel` feature.
+pub fn poseidon_fields_batch(inputs: &[Vec<Fr>]) -> Vec<Result<Fr, PoseidonError>> {
+    let poseidon = poseidon_default();
+    utils::map(inputs, |input| poseidon.hash_slice(input))
+}
+
+/// Hashes each input independently; the input length is checked once for the whole batch.
+pub fn poseidon_fields_batch_fixed<const N: usize>(
+    inputs: &[[Fr; N]],
+) -> Result<Vec<Fr>, PoseidonError> {
+    let poseidon = poseidon_default();
+    poseidon.hash_batch_fixed(inputs)
 }
 
 pub fn poseidon_bytes(input_bytes: &[u8]) -> Result<Fr, PoseidonError> {
@@ -23,6 +62,17 @@ pub fn compose_and_poseidon(
     num_composed_chunks: usize,
     bits_of_chunk: u128,
 ) -> Result<Fr, PoseidonError> {
+    let composed_fields = compose_fields(input_fields, num_composed_chunks, bits_of_chunk);
+    poseidon_fields(&composed_fields)
+}
+
+/// Composes each group of `num_composed_chunks` inputs into one element, little-endian
+/// with `bits_of_chunk` bits per input, as `compose_and_poseidon` does before hashing.
+pub fn compose_fields(
+    input_fields: &[Fr],
+    num_composed_chunks: usize,
+    bits_of_chunk: u128,
+) -> Vec<Fr> {
     let mut composed_fields = Vec::new();
     for fields in input_fields.chunks(num_composed_chunks) {
         let mut sum = Fr::ZERO;
@@ -33,10 +83,10 @@ pub fn compose_and_poseidon(
         }
         composed_fields.push(sum);
     }
-    poseidon_fields(&composed_fields)
+    composed_fields
 }
 
-fn poseidon_default() -> &'static Poseidon {
+pub(crate) fn poseidon_default() -> &'static Poseidon {
     static POSEIDON: OnceCell<Poseidon> = OnceCell::new();
 
     POSEIDON
@@ -295,6 +345,32 @@ mod tests {
         poseidon_fields(&big_arr).expect_err("Wrong inputs length");
     }
 
+    #[test]
+    fn test_batch() {
+        let inputs: Vec<Vec<Fr>> = (1..=17)
+            .map(|len| (0..len).map(|i| Fr::from(i as u64 + 1)).collect())
+            .collect();
+        let hashes = poseidon_fields_batch(&inputs);
+        assert_eq!(hashes.len(), 17);
+        for (input, hash) in inputs.iter().zip(hashes.iter()) {
+            match hash {
+                Ok(hash) => assert_eq!(*hash, poseidon_fields(input).unwrap()),
+                Err(_) => assert_eq!(input.len(), 17),
+            }
+        }
+
+        let inputs: Vec<[Fr; 3]> = (0..600u64)
+            .map(|i| [Fr::from(i), Fr::from(i + 1), Fr::from(i + 2)])
+            .collect();
+        let hashes = poseidon_fields_batch_fixed(&inputs).unwrap();
+        assert_eq!(hashes.len(), 600);
+        for (input, hash) in inputs.iter().zip(hashes.iter()) {
+            assert_eq!(*hash, poseidon_fields(input).unwrap());
+        }
+        assert!(poseidon_fields_batch_fixed::<3>(&[]).unwrap().is_empty());
+        poseidon_fields_batch_fixed::<0>(&[[]]).expect_err("Wrong inputs length");
+    }
+
     #[test]
     fn test_compose_poseidon() {
         let b0: Fr = Fr::from_str_vartime("0").unwrap();
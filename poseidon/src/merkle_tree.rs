@@ -0,0 +1,513 @@
+use crate::storage::{MemoryStore, NodeKey, NodeStore, StorageError, METADATA_LEVEL};
+use crate::utils::fr_to_u64;
+use crate::{poseidon_fields, poseidon_fields_batch_fixed, Fr, PoseidonError};
+use halo2curves::ff::*;
+use thiserror::Error;
+
+/// Maximum supported tree depth, so that leaf indices fit in a `u64`.
+pub const MAX_DEPTH: usize = 63;
+
+const DEPTH_KEY: NodeKey = (METADATA_LEVEL, 0);
+
+#[derive(Error, Debug)]
+pub enum MerkleTreeError {
+    #[error("Invalid depth: depth must be between 1 and `{0}` but got `{1}`")]
+    InvalidDepth(usize, usize),
+    #[error("Leaf index out of range: capacity is `{0}` but got index `{1}`")]
+    IndexOutOfRange(u64, u64),
+    #[error("Tree is full: capacity is `{0}`")]
+    TreeFull(u64),
+    #[error("Size overflow: `{0}` leaves take more than `u64::MAX` nodes")]
+    SizeOverflow(u64),
+    #[error("Value already exists in the tree")]
+    DuplicateValue,
+    #[error("Invalid proof: {0}")]
+    InvalidProof(&'static str),
+    #[error("Store is not empty")]
+    StoreNotEmpty,
+    #[error("Depth mismatch: store holds a tree of depth `{0}` but got `{1}`")]
+    DepthMismatch(u64, usize),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Poseidon(#[from] PoseidonError),
+}
+
+/// Hash of an inner node, `Poseidon(left, right)`.
+pub fn hash_pair(left: Fr, right: Fr) -> Result<Fr, PoseidonError> {
+    poseidon_fields(&[left, right])
+}
+
+/// Roots of empty subtrees: `zeros[0]` is the empty leaf (zero) and `zeros[i + 1] = H(zeros[i], zeros[i])`.
+pub fn zero_hashes(depth: usize) -> Result<Vec<Fr>, PoseidonError> {
+    let mut zeros = vec![Fr::ZERO];
+    for i in 0..depth {
+        zeros.push(hash_pair(zeros[i], zeros[i])?);
+    }
+    Ok(zeros)
+}
+
+/// Hashes a level of nodes pairwise into the level above, padding with `zero` if needed.
+/// Runs across threads with the `parallel` feature.
+pub fn hash_level(nodes: &[Fr], zero: Fr) -> Result<Vec<Fr>, PoseidonError> {
+    let pairs: Vec<[Fr; 2]> = nodes
+        .chunks(2)
+        .map(|pair| [pair[0], *pair.get(1).unwrap_or(&zero)])
+        .collect();
+    poseidon_fields_batch_fixed(&pairs)
+}
+
+/// Sparse fixed-depth binary Merkle tree hashed with Poseidon.
+/// Level 0 holds the leaves and level `depth` holds the root; unset nodes are roots of empty subtrees.
+#[derive(Debug, Clone)]
+pub struct MerkleTree<S: NodeStore = MemoryStore> {
+    depth: usize,
+    zeros: Vec<Fr>,
+    root: Fr,
+    store: S,
+}
+
+impl MerkleTree<MemoryStore> {
+    pub fn new(depth: usize) -> Result<MerkleTree, MerkleTreeError> {
+        MerkleTree::with_store(depth, MemoryStore::new())
+    }
+
+    /// Builds a tree whose first leaves are `leaves`, level by level.
+    pub fn from_leaves(depth: usize, leaves: &[Fr]) -> Result<MerkleTree, MerkleTreeError> {
+        MerkleTree::from_leaves_with_store(depth, leaves, MemoryStore::new())
+    }
+}
+
+impl<S: NodeStore> MerkleTree<S> {
+    /// Opens a tree on top of `store`, resuming from the nodes it already holds.
+    pub fn with_store(depth: usize, mut store: S) -> Result<MerkleTree<S>, MerkleTreeError> {
+        if depth == 0 || depth > MAX_DEPTH {
+            return Err(MerkleTreeError::InvalidDepth(MAX_DEPTH, depth));
+        }
+        match store.get(DEPTH_KEY)? {
+            Some(stored) if stored != Fr::from(depth as u64) => {
+                return Err(MerkleTreeError::DepthMismatch(
+                    fr_to_u64(&stored).unwrap_or(u64::MAX),
+                    depth,
+                ));
+            }
+            Some(_) => {}
+            None => store.put(DEPTH_KEY, Fr::from(depth as u64))?,
+        }
+        let zeros = zero_hashes(depth)?;
+        let root = store.get((depth, 0))?.unwrap_or(zeros[depth]);
+        Ok(MerkleTree {
+            depth,
+            zeros,
+            root,
+            store,
+        })
+    }
+
+    /// Builds a tree on top of an empty `store` whose first leaves are `leaves`, level by level.
+    /// Much faster than repeated [`MerkleTree::update`] calls, and parallel with the `parallel` feature.
+    /// Fails with [`MerkleTreeError::StoreNotEmpty`] if `store` already holds nodes.
+    pub fn from_leaves_with_store(
+        depth: usize,
+        leaves: &[Fr],
+        store: S,
+    ) -> Result<MerkleTree<S>, MerkleTreeError> {
+        if !store.is_empty() {
+            return Err(MerkleTreeError::StoreNotEmpty);
+        }
+        let mut tree = MerkleTree::with_store(depth, store)?;
+        if leaves.len() as u64 > tree.capacity() {
+            return Err(MerkleTreeError::TreeFull(tree.capacity()));
+        }
+        let mut nodes = leaves.to_vec();
+        for level in 0..=depth {
+            for (index, node) in nodes.iter().enumerate() {
+                tree.store.put((level, index as u64), *node)?;
+            }
+            if level < depth && !nodes.is_empty() {
+                nodes = hash_level(&nodes, tree.zeros[level])?;
+            }
+        }
+        tree.root = tree.node(depth, 0)?;
+        Ok(tree)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn capacity(&self) -> u64 {
+        1u64 << self.depth
+    }
+
+    pub fn root(&self) -> Fr {
+        self.root
+    }
+
+    pub fn node(&self, level: usize, index: u64) -> Result<Fr, MerkleTreeError> {
+        Ok(self.store.get((level, index))?.unwrap_or(self.zeros[level]))
+    }
+
+    pub fn leaf(&self, index: u64) -> Result<Fr, MerkleTreeError> {
+        self.check_index(index)?;
+        self.node(0, index)
+    }
+
+    pub fn update(&mut self, index: u64, leaf: Fr) -> Result<(), MerkleTreeError> {
+        self.check_index(index)?;
+        let mut index = index;
+        let mut node = leaf;
+        self.store.put((0, index), node)?;
+        for level in 0..self.depth {
+            node = if index & 1 == 0 {
+                hash_pair(node, self.node(level, index + 1)?)?
+            } else {
+                hash_pair(self.node(level, index - 1)?, node)?
+            };
+            index /= 2;
+            self.store.put((level + 1, index), node)?;
+        }
+        self.root = node;
+        Ok(())
+    }
+
+    pub fn proof(&self, index: u64) -> Result<MerkleProof, MerkleTreeError> {
+        self.check_index(index)?;
+        let siblings = (0..self.depth)
+            .map(|level| self.node(level, (index >> level) ^ 1))
+            .collect::<Result<_, _>>()?;
+        Ok(MerkleProof {
+            leaf_index: index,
+            siblings,
+        })
+    }
+
+    /// Proof of all leaves at `indices` at once, see [`MerkleMultiProof`].
+    pub fn multiproof(&self, indices: &[u64]) -> Result<MerkleMultiProof, MerkleTreeError> {
+        let mut leaf_indices = indices.to_vec();
+        leaf_indices.sort_unstable();
+        leaf_indices.dedup();
+        if leaf_indices.is_empty() {
+            return Err(MerkleTreeError::InvalidProof("no leaf to prove"));
+        }
+        for index in leaf_indices.iter() {
+            self.check_index(*index)?;
+        }
+
+        let mut siblings = Vec::new();
+        let mut level_indices = leaf_indices.clone();
+        for level in 0..self.depth {
+            let mut parents = Vec::new();
+            let mut i = 0;
+            while i < level_indices.len() {
+                let index = level_indices[i];
+                if index & 1 == 0 && level_indices.get(i + 1) == Some(&(index + 1)) {
+                    i += 2;
+                } else {
+                    siblings.push(self.node(level, index ^ 1)?);
+                    i += 1;
+                }
+                parents.push(index >> 1);
+            }
+            level_indices = parents;
+        }
+        Ok(MerkleMultiProof {
+            depth: self.depth,
+            leaf_indices,
+            siblings,
+        })
+    }
+
+    pub fn flush(&mut self) -> Result<(), MerkleTreeError> {
+        Ok(self.store.flush()?)
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub(crate) fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    fn check_index(&self, index: u64) -> Result<(), MerkleTreeError> {
+        if index >= self.capacity() {
+            return Err(MerkleTreeError::IndexOutOfRange(self.capacity(), index));
+        }
+        Ok(())
+    }
+}
+
+/// Inclusion proof of a single leaf, with siblings ordered from the leaf level up to the root.
+///
+/// Leaves and inner nodes are hashed alike, so verifiers pass the depth of their tree:
+/// a shorter proof would otherwise present an inner node as a leaf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub leaf_index: u64,
+    pub siblings: Vec<Fr>,
+}
+
+impl MerkleProof {
+    /// Recomputes the root of a tree of `depth` from `leaf`.
+    pub fn compute_root(&self, depth: usize, leaf: Fr) -> Result<Fr, MerkleTreeError> {
+        if self.siblings.len() != depth {
+            return Err(MerkleTreeError::InvalidProof("wrong depth"));
+        }
+        if depth < 64 && self.leaf_index >> depth != 0 {
+            return Err(MerkleTreeError::InvalidProof("leaf index out of range"));
+        }
+        let mut node = leaf;
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            node = if (self.leaf_index >> level) & 1 == 0 {
+                hash_pair(node, *sibling)?
+            } else {
+                hash_pair(*sibling, node)?
+            };
+        }
+        Ok(node)
+    }
+
+    pub fn verify(&self, depth: usize, root: Fr, leaf: Fr) -> Result<bool, MerkleTreeError> {
+        Ok(self.compute_root(depth, leaf)? == root)
+    }
+}
+
+/// Inclusion proof of several leaves against the same root.
+/// Siblings that can be recomputed from the proven leaves are omitted; the remaining ones
+/// are listed level by level from the leaves up, in increasing index order within a level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleMultiProof {
+    pub depth: usize,
+    /// Sorted and deduplicated indices of the proven leaves.
+    pub leaf_indices: Vec<u64>,
+    pub siblings: Vec<Fr>,
+}
+
+impl MerkleMultiProof {
+    /// Recomputes the root of a tree of `depth` from `leaves`, given in the order of
+    /// `leaf_indices`. Each level costs one Poseidon call per distinct parent node.
+    pub fn compute_root(&self, depth: usize, leaves: &[Fr]) -> Result<Fr, MerkleTreeError> {
+        if self.depth != depth {
+            return Err(MerkleTreeError::InvalidProof("wrong depth"));
+        }
+        if leaves.len() != self.leaf_indices.len() || leaves.is_empty() {
+            return Err(MerkleTreeError::InvalidProof("wrong number of leaves"));
+        }
+        if self.leaf_indices.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(MerkleTreeError::InvalidProof("leaf indices are not sorted"));
+        }
+        let mut nodes: Vec<(u64, Fr)> = self
+            .leaf_indices
+            .iter()
+            .copied()
+            .zip(leaves.iter().copied())
+            .collect();
+        let mut siblings = self.siblings.iter();
+        for _ in 0..self.depth {
+            let mut parents = Vec::new();
+            let mut i = 0;
+            while i < nodes.len() {
+                let (index, node) = nodes[i];
+                let parent = match nodes.get(i + 1) {
+                    Some((next_index, next)) if index & 1 == 0 && *next_index == index + 1 => {
+                        i += 2;
+                        hash_pair(node, *next)?
+                    }
+                    _ => {
+                        i += 1;
+                        let sibling = *siblings
+                            .next()
+                            .ok_or(MerkleTreeError::InvalidProof("missing sibling"))?;
+                        if index & 1 == 0 {
+                            hash_pair(node, sibling)?
+                        } else {
+                            hash_pair(sibling, node)?
+                        }
+                    }
+                };
+                parents.push((index >> 1, parent));
+            }
+            nodes = parents;
+        }
+        if siblings.next().is_some() {
+            return Err(MerkleTreeError::InvalidProof("unused siblings"));
+        }
+        match nodes.as_slice() {
+            [(0, root)] => Ok(*root),
+            _ => Err(MerkleTreeError::InvalidProof("leaf index out of range")),
+        }
+    }
+
+    pub fn verify(&self, depth: usize, root: Fr, leaves: &[Fr]) -> Result<bool, MerkleTreeError> {
+        Ok(self.compute_root(depth, leaves)? == root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_root() {
+        let tree = MerkleTree::new(4).unwrap();
+        let zeros = zero_hashes(4).unwrap();
+        assert_eq!(tree.root(), zeros[4]);
+        assert_eq!(zeros[1], poseidon_fields(&[Fr::ZERO, Fr::ZERO]).unwrap());
+    }
+
+    #[test]
+    fn test_update_and_proof() {
+        let mut tree = MerkleTree::new(3).unwrap();
+        let leaves: Vec<Fr> = (1..=5).map(Fr::from).collect();
+        for (i, leaf) in leaves.iter().enumerate() {
+            tree.update(i as u64, *leaf).unwrap();
+        }
+
+        let mut level = leaves.clone();
+        level.resize(8, Fr::ZERO);
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| hash_pair(pair[0], pair[1]).unwrap())
+                .collect();
+        }
+        assert_eq!(tree.root(), level[0]);
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            let proof = tree.proof(i as u64).unwrap();
+            assert!(proof.verify(3, tree.root(), *leaf).unwrap());
+            assert!(!proof.verify(3, tree.root(), Fr::from(42)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_from_leaves() {
+        let leaves: Vec<Fr> = (1..=11).map(Fr::from).collect();
+        let tree = MerkleTree::from_leaves(5, &leaves).unwrap();
+        let mut expected = MerkleTree::new(5).unwrap();
+        for (i, leaf) in leaves.iter().enumerate() {
+            expected.update(i as u64, *leaf).unwrap();
+        }
+        assert_eq!(tree.root(), expected.root());
+        assert_eq!(tree.proof(10).unwrap(), expected.proof(10).unwrap());
+        assert_eq!(tree.proof(20).unwrap(), expected.proof(20).unwrap());
+
+        let mut store = MemoryStore::new();
+        store.put((0, 0), Fr::ONE).unwrap();
+        assert!(matches!(
+            MerkleTree::from_leaves_with_store(5, &leaves, store),
+            Err(MerkleTreeError::StoreNotEmpty)
+        ));
+
+        let empty = MerkleTree::from_leaves(5, &[]).unwrap();
+        assert_eq!(empty.root(), zero_hashes(5).unwrap()[5]);
+        MerkleTree::from_leaves(3, &leaves).expect_err("Tree is full");
+    }
+
+    #[test]
+    fn test_forged_shorter_proof() {
+        let leaves: Vec<Fr> = (1..=8).map(Fr::from).collect();
+        let tree = MerkleTree::from_leaves(3, &leaves).unwrap();
+        let root = tree.root();
+
+        // Inner node (1, 1) = H(leaf 2, leaf 3) passed off as a leaf of a depth-2 tree.
+        let forged = MerkleProof {
+            leaf_index: 1,
+            siblings: vec![tree.node(1, 0).unwrap(), tree.node(2, 1).unwrap()],
+        };
+        let inner = tree.node(1, 1).unwrap();
+        assert_eq!(
+            forged.compute_root(2, inner).unwrap(),
+            root,
+            "Forgery of a verifier trusting the proof length"
+        );
+        forged.verify(3, root, inner).expect_err("Wrong depth");
+
+        let mut high_index = tree.proof(2).unwrap();
+        high_index.leaf_index |= 1 << 3;
+        high_index
+            .verify(3, root, Fr::from(3))
+            .expect_err("Leaf index out of range");
+    }
+
+    #[test]
+    fn test_wrong_index_and_depth() {
+        let mut tree = MerkleTree::new(2).unwrap();
+        tree.update(4, Fr::ONE).expect_err("Index out of range");
+        tree.leaf(4).expect_err("Index out of range");
+        tree.proof(4).expect_err("Index out of range");
+        MerkleTree::new(0).expect_err("Invalid depth");
+        MerkleTree::new(MAX_DEPTH + 1).expect_err("Invalid depth");
+    }
+
+    #[test]
+    fn test_multiproof() {
+        let mut tree = MerkleTree::new(4).unwrap();
+        for i in 0..10 {
+            tree.update(i, Fr::from(i + 100)).unwrap();
+        }
+        let root = tree.root();
+
+        let proof = tree.multiproof(&[0, 1, 2, 3]).unwrap();
+        assert_eq!(
+            proof.siblings,
+            vec![tree.node(2, 1).unwrap(), tree.node(3, 1).unwrap()]
+        );
+
+        let proof = tree.multiproof(&[9, 2, 3, 7, 2]).unwrap();
+        assert_eq!(proof.leaf_indices, vec![2, 3, 7, 9]);
+        let leaves: Vec<Fr> = proof
+            .leaf_indices
+            .iter()
+            .map(|i| Fr::from(i + 100))
+            .collect();
+        assert!(proof.verify(4, root, &leaves).unwrap());
+        let single_siblings = 4 * tree.depth();
+        assert!(proof.siblings.len() < single_siblings);
+
+        let mut wrong_leaves = leaves.clone();
+        wrong_leaves[3] = Fr::from(42);
+        assert!(!proof.verify(4, root, &wrong_leaves).unwrap());
+        proof
+            .verify(4, root, &leaves[1..])
+            .expect_err("Wrong number of leaves");
+
+        let mut extra_sibling = proof.clone();
+        extra_sibling.siblings.push(Fr::ZERO);
+        extra_sibling
+            .verify(4, root, &leaves)
+            .expect_err("Unused siblings");
+        let mut missing_sibling = proof;
+        missing_sibling.siblings.pop();
+        missing_sibling
+            .verify(4, root, &leaves)
+            .expect_err("Missing sibling");
+
+        let proof = tree.multiproof(&[2, 3]).unwrap();
+        proof
+            .verify(3, root, &leaves[..2])
+            .expect_err("Wrong depth");
+
+        tree.multiproof(&[]).expect_err("No leaf to prove");
+        tree.multiproof(&[1, 16]).expect_err("Index out of range");
+    }
+
+    #[test]
+    fn test_reopen_store() {
+        let mut tree = MerkleTree::new(8).unwrap();
+        tree.update(3, Fr::from(3)).unwrap();
+        tree.update(200, Fr::from(200)).unwrap();
+        let root = tree.root();
+
+        let tree = MerkleTree::with_store(8, tree.into_store()).unwrap();
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.leaf(200).unwrap(), Fr::from(200));
+        assert!(tree.proof(3).unwrap().verify(8, root, Fr::from(3)).unwrap());
+        MerkleTree::with_store(9, tree.into_store()).expect_err("Depth mismatch");
+    }
+}
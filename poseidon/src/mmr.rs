@@ -0,0 +1,472 @@
+use crate::merkle_tree::{hash_pair, MerkleTreeError};
+use crate::storage::{MemoryStore, NodeKey, NodeStore, StorageError, METADATA_LEVEL};
+use crate::utils::fr_to_u64;
+use crate::{Fr, PoseidonError};
+use halo2curves::ff::*;
+use std::collections::BTreeMap;
+
+const LEAF_COUNT_KEY: NodeKey = (METADATA_LEVEL, 1);
+
+/// Maximum height of a peak, so that leaf counts fit in a `u64`.
+const MAX_HEIGHT: usize = 63;
+
+/// Number of nodes of an MMR holding `num_leaves` leaves, an error if it does not fit in a `u64`.
+pub fn mmr_size(num_leaves: u64) -> Result<u64, MerkleTreeError> {
+    // `2 * n - popcount(n)`, subtracting first so that sizes up to `u64::MAX` are reachable.
+    num_leaves
+        .checked_add(num_leaves - num_leaves.count_ones() as u64)
+        .ok_or(MerkleTreeError::SizeOverflow(num_leaves))
+}
+
+/// Peaks of an MMR holding `num_leaves` leaves as `(height, index)`, from left to right.
+/// A node at `(height, index)` is the root of the perfect subtree over leaves
+/// `index * 2^height .. (index + 1) * 2^height`.
+pub fn peak_positions(num_leaves: u64) -> Vec<(usize, u64)> {
+    let mut peaks = Vec::new();
+    let mut start = 0u64;
+    for height in (0..=MAX_HEIGHT).rev() {
+        if (num_leaves >> height) & 1 == 1 {
+            peaks.push((height, start >> height));
+            start += 1 << height;
+        }
+    }
+    peaks
+}
+
+/// MMR root: `Poseidon(num_leaves, bag)`, where the peaks are bagged from right to left
+/// with `bag = Poseidon(peak, bag)`, starting from the rightmost peak (zero if there is none).
+pub fn bag_peaks(num_leaves: u64, peaks: &[Fr]) -> Result<Fr, PoseidonError> {
+    let mut bag = match peaks.last() {
+        Some(peak) => *peak,
+        None => Fr::ZERO,
+    };
+    for peak in peaks.iter().rev().skip(1) {
+        bag = hash_pair(*peak, bag)?;
+    }
+    hash_pair(Fr::from(num_leaves), bag)
+}
+
+/// Append-only Merkle Mountain Range hashed with Poseidon.
+/// Nodes are stored under `(height, index)`, like the levels of a [`crate::merkle_tree::MerkleTree`].
+#[derive(Debug, Clone)]
+pub struct MerkleMountainRange<S: NodeStore = MemoryStore> {
+    num_leaves: u64,
+    store: S,
+}
+
+impl MerkleMountainRange<MemoryStore> {
+    pub fn new() -> MerkleMountainRange {
+        MerkleMountainRange {
+            num_leaves: 0,
+            store: MemoryStore::new(),
+        }
+    }
+}
+
+impl Default for MerkleMountainRange<MemoryStore> {
+    fn default() -> Self {
+        MerkleMountainRange::new()
+    }
+}
+
+impl<S: NodeStore> MerkleMountainRange<S> {
+    /// Opens an MMR on top of `store`, resuming from the leaves it already holds.
+    pub fn with_store(store: S) -> Result<MerkleMountainRange<S>, MerkleTreeError> {
+        let num_leaves = match store.get(LEAF_COUNT_KEY)? {
+            Some(num_leaves) => fr_to_u64(&num_leaves).ok_or_else(|| {
+                StorageError::Corrupted("leaf count does not fit in u64".to_string())
+            })?,
+            None => 0,
+        };
+        Ok(MerkleMountainRange { num_leaves, store })
+    }
+
+    pub fn num_leaves(&self) -> u64 {
+        self.num_leaves
+    }
+
+    pub fn size(&self) -> Result<u64, MerkleTreeError> {
+        mmr_size(self.num_leaves)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_leaves == 0
+    }
+
+    /// Appends a leaf and returns its index.
+    pub fn push(&mut self, leaf: Fr) -> Result<u64, MerkleTreeError> {
+        let leaf_index = self.num_leaves;
+        if leaf_index == u64::MAX {
+            return Err(MerkleTreeError::TreeFull(u64::MAX));
+        }
+        let mut height = 0;
+        let mut index = leaf_index;
+        let mut node = leaf;
+        self.store.put((height, index), node)?;
+        while index & 1 == 1 {
+            node = hash_pair(self.node(height, index - 1)?, node)?;
+            height += 1;
+            index >>= 1;
+            self.store.put((height, index), node)?;
+        }
+        self.num_leaves += 1;
+        self.store.put(LEAF_COUNT_KEY, Fr::from(self.num_leaves))?;
+        Ok(leaf_index)
+    }
+
+    pub fn leaf(&self, index: u64) -> Result<Fr, MerkleTreeError> {
+        if index >= self.num_leaves {
+            return Err(MerkleTreeError::IndexOutOfRange(self.num_leaves, index));
+        }
+        self.node(0, index)
+    }
+
+    pub fn peaks(&self) -> Result<Vec<Fr>, MerkleTreeError> {
+        self.peaks_at(self.num_leaves)
+    }
+
+    /// Peaks of the MMR as it was when it held `num_leaves` leaves.
+    pub fn peaks_at(&self, num_leaves: u64) -> Result<Vec<Fr>, MerkleTreeError> {
+        self.check_num_leaves(num_leaves)?;
+        peak_positions(num_leaves)
+            .into_iter()
+            .map(|(height, index)| self.node(height, index))
+            .collect()
+    }
+
+    pub fn root(&self) -> Result<Fr, MerkleTreeError> {
+        self.root_at(self.num_leaves)
+    }
+
+    /// Root of the MMR as it was when it held `num_leaves` leaves.
+    pub fn root_at(&self, num_leaves: u64) -> Result<Fr, MerkleTreeError> {
+        Ok(bag_peaks(num_leaves, &self.peaks_at(num_leaves)?)?)
+    }
+
+    /// Inclusion proof of a leaf against the root at `num_leaves` leaves.
+    pub fn proof(&self, leaf_index: u64, num_leaves: u64) -> Result<MmrProof, MerkleTreeError> {
+        self.check_num_leaves(num_leaves)?;
+        if leaf_index >= num_leaves {
+            return Err(MerkleTreeError::IndexOutOfRange(num_leaves, leaf_index));
+        }
+        let (siblings, peaks) = self.prove(&[(0, leaf_index)], num_leaves)?;
+        Ok(MmrProof {
+            num_leaves,
+            leaf_index,
+            siblings,
+            peaks,
+        })
+    }
+
+    /// Proof that the MMR at `new_num_leaves` leaves extends the MMR at `old_num_leaves` leaves.
+    pub fn consistency_proof(
+        &self,
+        old_num_leaves: u64,
+        new_num_leaves: u64,
+    ) -> Result<MmrConsistencyProof, MerkleTreeError> {
+        self.check_num_leaves(new_num_leaves)?;
+        if old_num_leaves > new_num_leaves {
+            return Err(MerkleTreeError::IndexOutOfRange(
+                new_num_leaves,
+                old_num_leaves,
+            ));
+        }
+        let old_positions = peak_positions(old_num_leaves);
+        let (siblings, peaks) = self.prove(&old_positions, new_num_leaves)?;
+        Ok(MmrConsistencyProof {
+            old_num_leaves,
+            new_num_leaves,
+            old_peaks: self.peaks_at(old_num_leaves)?,
+            siblings,
+            peaks,
+        })
+    }
+
+    pub fn flush(&mut self) -> Result<(), MerkleTreeError> {
+        Ok(self.store.flush()?)
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    fn node(&self, height: usize, index: u64) -> Result<Fr, MerkleTreeError> {
+        Ok(self.store.get((height, index))?.ok_or_else(|| {
+            StorageError::Corrupted(format!("missing node ({}, {})", height, index))
+        })?)
+    }
+
+    fn check_num_leaves(&self, num_leaves: u64) -> Result<(), MerkleTreeError> {
+        if num_leaves > self.num_leaves {
+            return Err(MerkleTreeError::IndexOutOfRange(
+                self.num_leaves,
+                num_leaves,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Siblings and uncovered peaks needed to hash the nodes at `positions` up to the peaks.
+    fn prove(
+        &self,
+        positions: &[(usize, u64)],
+        num_leaves: u64,
+    ) -> Result<(Vec<Fr>, Vec<Fr>), MerkleTreeError> {
+        let mut known = Vec::new();
+        for (height, index) in positions.iter() {
+            known.push((*height, *index, self.node(*height, *index)?));
+        }
+        let mut siblings = Vec::new();
+        let reached = fold_to_peaks(&known, num_leaves, |height, index| {
+            let sibling = self.node(height, index)?;
+            siblings.push(sibling);
+            Ok(sibling)
+        })?;
+        let mut peaks = Vec::new();
+        for ((height, index), peak) in peak_positions(num_leaves).into_iter().zip(reached) {
+            if peak.is_none() {
+                peaks.push(self.node(height, index)?);
+            }
+        }
+        Ok((siblings, peaks))
+    }
+}
+
+/// Inclusion proof of a leaf in an MMR of `num_leaves` leaves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MmrProof {
+    pub num_leaves: u64,
+    pub leaf_index: u64,
+    /// Siblings from the leaf up to its peak.
+    pub siblings: Vec<Fr>,
+    /// The other peaks, from left to right.
+    pub peaks: Vec<Fr>,
+}
+
+impl MmrProof {
+    pub fn compute_root(&self, leaf: Fr) -> Result<Fr, MerkleTreeError> {
+        if self.leaf_index >= self.num_leaves {
+            return Err(MerkleTreeError::IndexOutOfRange(
+                self.num_leaves,
+                self.leaf_index,
+            ));
+        }
+        root_from_known(
+            &[(0, self.leaf_index, leaf)],
+            self.num_leaves,
+            &self.siblings,
+            &self.peaks,
+        )
+    }
+
+    pub fn verify(&self, root: Fr, leaf: Fr) -> Result<bool, MerkleTreeError> {
+        Ok(self.compute_root(leaf)? == root)
+    }
+}
+
+/// Proof that the MMR at `new_num_leaves` leaves is an extension of the one at `old_num_leaves`:
+/// the old peaks are hashed up to the new peaks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MmrConsistencyProof {
+    pub old_num_leaves: u64,
+    pub new_num_leaves: u64,
+    pub old_peaks: Vec<Fr>,
+    pub siblings: Vec<Fr>,
+    /// New peaks that do not cover any old peak, from left to right.
+    pub peaks: Vec<Fr>,
+}
+
+impl MmrConsistencyProof {
+    pub fn verify(&self, old_root: Fr, new_root: Fr) -> Result<bool, MerkleTreeError> {
+        if self.old_num_leaves > self.new_num_leaves {
+            return Err(MerkleTreeError::InvalidProof("old size exceeds new size"));
+        }
+        let old_positions = peak_positions(self.old_num_leaves);
+        if old_positions.len() != self.old_peaks.len() {
+            return Err(MerkleTreeError::InvalidProof("wrong number of old peaks"));
+        }
+        if bag_peaks(self.old_num_leaves, &self.old_peaks)? != old_root {
+            return Ok(false);
+        }
+        let known: Vec<(usize, u64, Fr)> = old_positions
+            .into_iter()
+            .zip(self.old_peaks.iter())
+            .map(|((height, index), peak)| (height, index, *peak))
+            .collect();
+        let root = root_from_known(&known, self.new_num_leaves, &self.siblings, &self.peaks)?;
+        Ok(root == new_root)
+    }
+}
+
+fn root_from_known(
+    known: &[(usize, u64, Fr)],
+    num_leaves: u64,
+    siblings: &[Fr],
+    peaks: &[Fr],
+) -> Result<Fr, MerkleTreeError> {
+    let mut siblings = siblings.iter();
+    let reached = fold_to_peaks(known, num_leaves, |_, _| {
+        siblings
+            .next()
+            .copied()
+            .ok_or(MerkleTreeError::InvalidProof("missing sibling"))
+    })?;
+    if siblings.next().is_some() {
+        return Err(MerkleTreeError::InvalidProof("unused siblings"));
+    }
+    let mut peaks = peaks.iter();
+    let mut all_peaks = Vec::new();
+    for peak in reached {
+        match peak {
+            Some(peak) => all_peaks.push(peak),
+            None => all_peaks.push(
+                *peaks
+                    .next()
+                    .ok_or(MerkleTreeError::InvalidProof("missing peak"))?,
+            ),
+        }
+    }
+    if peaks.next().is_some() {
+        return Err(MerkleTreeError::InvalidProof("unused peaks"));
+    }
+    Ok(bag_peaks(num_leaves, &all_peaks)?)
+}
+
+/// Hashes the `known` nodes up to the peaks of an MMR of `num_leaves` leaves, taking the
+/// missing siblings from `sibling` in a deterministic order (height by height, by index).
+/// Returns every peak reached from a known node, `None` for the others.
+fn fold_to_peaks<F>(
+    known: &[(usize, u64, Fr)],
+    num_leaves: u64,
+    mut sibling: F,
+) -> Result<Vec<Option<Fr>>, MerkleTreeError>
+where
+    F: FnMut(usize, u64) -> Result<Fr, MerkleTreeError>,
+{
+    let peaks = peak_positions(num_leaves);
+    let mut reached = vec![None; peaks.len()];
+    let mut levels: Vec<BTreeMap<u64, Fr>> = vec![BTreeMap::new(); MAX_HEIGHT + 1];
+    for (height, index, node) in known.iter() {
+        let end = index
+            .checked_add(1)
+            .and_then(|end| end.checked_mul(1 << (*height).min(MAX_HEIGHT)));
+        let exists = *height <= MAX_HEIGHT && matches!(end, Some(end) if end <= num_leaves);
+        if !exists {
+            return Err(MerkleTreeError::InvalidProof("node out of range"));
+        }
+        levels[*height].insert(*index, *node);
+    }
+    for height in 0..=MAX_HEIGHT {
+        let mut nodes = std::mem::take(&mut levels[height]).into_iter().peekable();
+        while let Some((index, node)) = nodes.next() {
+            if let Some(k) = peaks.iter().position(|peak| *peak == (height, index)) {
+                reached[k] = Some(node);
+                continue;
+            }
+            // A node that is not a peak always has its parent in the MMR.
+            let parent = if index & 1 == 0 {
+                let right = match nodes.peek() {
+                    Some((next, right)) if *next == index + 1 => {
+                        let right = *right;
+                        nodes.next();
+                        right
+                    }
+                    _ => sibling(height, index + 1)?,
+                };
+                hash_pair(node, right)?
+            } else {
+                hash_pair(sibling(height, index - 1)?, node)?
+            };
+            levels[height + 1].insert(index >> 1, parent);
+        }
+    }
+    Ok(reached)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mmr_with_leaves(n: u64) -> MerkleMountainRange {
+        let mut mmr = MerkleMountainRange::new();
+        for i in 0..n {
+            assert_eq!(mmr.push(Fr::from(i + 1)).unwrap(), i);
+        }
+        mmr
+    }
+
+    #[test]
+    fn test_peaks_and_root() {
+        assert_eq!(peak_positions(11), vec![(3, 0), (1, 4), (0, 10)]);
+        assert_eq!(mmr_size(11).unwrap(), 19);
+        assert_eq!(mmr_size(1 << 63).unwrap(), u64::MAX);
+        assert!(matches!(
+            mmr_size((1 << 63) + 1),
+            Err(MerkleTreeError::SizeOverflow(_))
+        ));
+
+        let mmr = mmr_with_leaves(3);
+        let (l0, l1, l2) = (Fr::from(1), Fr::from(2), Fr::from(3));
+        let peak = hash_pair(l0, l1).unwrap();
+        assert_eq!(mmr.peaks().unwrap(), vec![peak, l2]);
+        let bag = hash_pair(peak, l2).unwrap();
+        assert_eq!(mmr.root().unwrap(), hash_pair(Fr::from(3), bag).unwrap());
+        assert_eq!(mmr.size().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_inclusion_proof() {
+        let mmr = mmr_with_leaves(11);
+        for num_leaves in 1..=11 {
+            let root = mmr.root_at(num_leaves).unwrap();
+            for leaf_index in 0..num_leaves {
+                let proof = mmr.proof(leaf_index, num_leaves).unwrap();
+                assert!(proof.verify(root, Fr::from(leaf_index + 1)).unwrap());
+                assert!(!proof.verify(root, Fr::from(100)).unwrap());
+            }
+        }
+        mmr.proof(5, 5).expect_err("Leaf index out of range");
+        mmr.proof(0, 12).expect_err("Size out of range");
+    }
+
+    #[test]
+    fn test_consistency_proof() {
+        let mmr = mmr_with_leaves(13);
+        for new in 0..=13 {
+            for old in 0..=new {
+                let proof = mmr.consistency_proof(old, new).unwrap();
+                let old_root = mmr.root_at(old).unwrap();
+                let new_root = mmr.root_at(new).unwrap();
+                assert!(proof.verify(old_root, new_root).unwrap());
+                assert!(!proof.verify(new_root, new_root).unwrap() || old == new);
+            }
+        }
+
+        let other = {
+            let mut other = mmr_with_leaves(5);
+            for i in 0..8 {
+                other.push(Fr::from(100 + i)).unwrap();
+            }
+            other
+        };
+        let proof = mmr.consistency_proof(5, 13).unwrap();
+        assert!(!proof
+            .verify(mmr.root_at(5).unwrap(), other.root().unwrap())
+            .unwrap());
+    }
+
+    #[test]
+    fn test_reopen_store() {
+        let mmr = mmr_with_leaves(6);
+        let root = mmr.root().unwrap();
+        let mut mmr = MerkleMountainRange::with_store(mmr.into_store()).unwrap();
+        assert_eq!(mmr.num_leaves(), 6);
+        assert_eq!(mmr.root().unwrap(), root);
+        mmr.push(Fr::from(7)).unwrap();
+        assert_eq!(mmr.root().unwrap(), mmr_with_leaves(7).root().unwrap());
+    }
+}
@@ -1,24 +1,47 @@
 use crate::constants::*;
+use crate::trace::*;
+use crate::utils::map;
 use crate::Fr;
 use halo2curves::ff::*;
-use std::ops::{AddAssign, MulAssign};
+use once_cell::sync::OnceCell;
+use std::ops::{AddAssign, MulAssign, SubAssign};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum PoseidonError {
     #[error("Wrong inputs length: max length is `{0}` but got `{1}`")]
     WrongInputsLength(usize, usize),
+    #[error("Wrong state width: width must be between 2 and `{0}` but got `{1}`")]
+    WrongStateWidth(usize, usize),
+    #[error("Domain tag too long: max length is 31 bytes but got `{0}`")]
+    DomainTagTooLong(usize),
 }
 
+/// Number of inputs hashed with the same state buffers by a thread in batch hashing.
+const BATCH_CHUNK_SIZE: usize = 256;
+
+/// Number of lanes advanced in lock-step by batch hashing.
+pub const BATCH_LANES: usize = 4;
+
+/// `1/5 mod (p - 1)` as little-endian limbs, so that `(x^5)^(1/5) = x` in `Fr`.
+const INV_ALPHA: [u64; 4] = [
+    0xcfe7f7a98ccccccd,
+    0x535cb9d394945a0d,
+    0x93736af8679aad17,
+    0x26b6a528b427b354,
+];
+
 #[derive(Debug, Clone)]
 pub struct Poseidon {
     constants: Constants,
+    /// Inverse MDS matrix of each width, computed on first use by the inverse permutation.
+    m_inv: Vec<OnceCell<Vec<Vec<Fr>>>>,
 }
 impl Poseidon {
     pub fn new() -> Poseidon {
-        Poseidon {
-            constants: load_constants(),
-        }
+        let constants = load_constants();
+        let m_inv = vec![OnceCell::new(); constants.m.len()];
+        Poseidon { constants, m_inv }
     }
     pub fn ark(&self, state: &mut Vec<Fr>, c: &Vec<Fr>, it: usize) {
         for i in 0..state.len() {
@@ -27,7 +50,7 @@ impl Poseidon {
     }
 
     pub fn sbox(&self, n_rounds_f: usize, n_rounds_p: usize, state: &mut Vec<Fr>, i: usize) {
-        if i < n_rounds_f / 2 || i >= n_rounds_f / 2 + n_rounds_p {
+        if round_type(n_rounds_f, n_rounds_p, i) == RoundType::Full {
             for j in 0..state.len() {
                 let aux = state[j];
                 state[j] = state[j].square();
@@ -56,26 +79,412 @@ impl Poseidon {
     }
 
     pub fn hash(&self, inp: Vec<Fr>) -> Result<Fr, PoseidonError> {
-        let t = inp.len() + 1;
-        // if inp.len() == 0 || inp.len() >= self.constants.n_rounds_p.len() - 1 {
-        if inp.is_empty() || inp.len() > self.constants.n_rounds_p.len() {
+        self.hash_slice(&inp)
+    }
+
+    /// Same as [`Poseidon::hash`], also recording the state after every ARK, S-box and MDS step.
+    pub fn hash_with_trace(&self, inp: Vec<Fr>) -> Result<Trace, PoseidonError> {
+        let mut state = vec![Fr::ZERO; inp.len() + 1];
+        state[1..].clone_from_slice(&inp);
+        let initial_state = state.clone();
+        let mut rows = Vec::new();
+        let output = self.hash_steps(&inp, |round, round_type, step, state| {
+            rows.push(TraceRow {
+                round,
+                round_type,
+                step,
+                state: state.clone(),
+            })
+        })?;
+        Ok(Trace {
+            inputs: inp,
+            initial_state,
+            rows,
+            output,
+        })
+    }
+
+    /// Same as [`Poseidon::hash`], without taking ownership of the inputs.
+    pub fn hash_slice(&self, inp: &[Fr]) -> Result<Fr, PoseidonError> {
+        self.hash_steps(inp, |_, _, _, _| {})
+    }
+
+    /// Hashes many inputs of the same length, checking the length and allocating the state once
+    /// per chunk of inputs. Runs across threads with the `parallel` feature.
+    pub fn hash_batch_fixed<const N: usize>(
+        &self,
+        inputs: &[[Fr; N]],
+    ) -> Result<Vec<Fr>, PoseidonError> {
+        self.check_inputs_len(N)?;
+        let chunks: Vec<&[[Fr; N]]> = inputs.chunks(BATCH_CHUNK_SIZE).collect();
+        let hashes = map(&chunks, |chunk| {
+            let mut hashes = Vec::with_capacity(chunk.len());
+            let mut state = vec![[Fr::ZERO; BATCH_LANES]; N + 1];
+            let mut scratch = vec![[Fr::ZERO; BATCH_LANES]; N + 1];
+            let lanes = chunk.chunks_exact(BATCH_LANES);
+            let remainder = lanes.remainder();
+            for lane_inputs in lanes {
+                load_lanes(&mut state, lane_inputs);
+                self.permute_lanes(&mut state, &mut scratch);
+                hashes.extend_from_slice(&state[0]);
+            }
+            let mut state = vec![Fr::ZERO; N + 1];
+            let mut scratch = vec![Fr::ZERO; N + 1];
+            for input in remainder {
+                state[0] = Fr::ZERO;
+                state[1..].copy_from_slice(input);
+                self.permute_state(&mut state, &mut scratch);
+                hashes.push(state[0]);
+            }
+            hashes
+        });
+        Ok(hashes.concat())
+    }
+
+    /// Hashes `L` inputs of the same length at once, one per lane. The states are kept as
+    /// structure-of-arrays (`state[i][lane]`) and every round step is applied to all lanes
+    /// in lock-step, so the field arithmetic of independent lanes can be interleaved.
+    /// Returns the same hashes as [`Poseidon::hash`] on each input.
+    pub fn hash_lanes<const N: usize, const L: usize>(
+        &self,
+        inputs: &[[Fr; N]; L],
+    ) -> Result<[Fr; L], PoseidonError> {
+        self.check_inputs_len(N)?;
+        let mut state = vec![[Fr::ZERO; L]; N + 1];
+        let mut scratch = vec![[Fr::ZERO; L]; N + 1];
+        load_lanes(&mut state, inputs);
+        self.permute_lanes(&mut state, &mut scratch);
+        Ok(state[0])
+    }
+
+    /// Forward permutation of a state of width `t = state.len()`, with the round constants and
+    /// MDS matrix of that width. `hash` is `permute([0, inputs...])[0]`.
+    pub fn permute(&self, state: &mut [Fr]) -> Result<(), PoseidonError> {
+        self.check_width(state.len())?;
+        let mut scratch = vec![Fr::ZERO; state.len()];
+        self.permute_state(state, &mut scratch);
+        Ok(())
+    }
+
+    /// Inverse of [`Poseidon::permute`]: rounds are undone in reverse order, each one applying
+    /// the inverse MDS matrix, the inverse S-box `x^(1/5)` and subtracting the round constants.
+    pub fn inverse_permute(&self, state: &mut [Fr]) -> Result<(), PoseidonError> {
+        self.check_width(state.len())?;
+        let t = state.len();
+        let c = &self.constants.c[t - 2];
+        let m_inv = self.m_inv[t - 2]
+            .get_or_init(|| invert_matrix(&self.constants.m[t - 2]).expect("MDS is invertible"));
+        let inverse_sbox = |x: &mut Fr| *x = x.pow_vartime(INV_ALPHA);
+
+        for (i, round_type) in self.rounds(t).rev() {
+            let new_state: Vec<Fr> = m_inv
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .zip(state.iter())
+                        .fold(Fr::ZERO, |acc, (mij, s)| acc + *mij * s)
+                })
+                .collect();
+            state.copy_from_slice(&new_state);
+            if round_type == RoundType::Full {
+                state.iter_mut().for_each(inverse_sbox);
+            } else {
+                inverse_sbox(&mut state[0]);
+            }
+            for (s, c) in state.iter_mut().zip(c[i * t..].iter()) {
+                s.sub_assign(c);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_width(&self, t: usize) -> Result<(), PoseidonError> {
+        if t < 2 || t > self.constants.m.len() + 1 {
+            return Err(PoseidonError::WrongStateWidth(
+                self.constants.m.len() + 1,
+                t,
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_inputs_len(&self, len: usize) -> Result<(), PoseidonError> {
+        if len == 0 || len > self.constants.n_rounds_p.len() {
             return Err(PoseidonError::WrongInputsLength(
                 self.constants.n_rounds_p.len(),
-                inp.len(),
+                len,
             ));
         }
-        let n_rounds_f = self.constants.n_rounds_f.clone();
-        let n_rounds_p = self.constants.n_rounds_p[t - 2].clone();
+        Ok(())
+    }
 
-        let mut state = vec![Fr::ZERO; t];
-        state[1..].clone_from_slice(&inp);
+    /// Rounds of the permutation of width `t` in order, with their index and type.
+    /// Every permutation and hashing path walks this schedule.
+    fn rounds(&self, t: usize) -> impl DoubleEndedIterator<Item = (usize, RoundType)> {
+        let n_rounds_f = self.constants.n_rounds_f;
+        let n_rounds_p = self.constants.n_rounds_p[t - 2];
+        (0..n_rounds_f + n_rounds_p).map(move |i| (i, round_type(n_rounds_f, n_rounds_p, i)))
+    }
+
+    /// `hash` with the public `ark`, `sbox` and `mix` steps, calling `on_step` with the
+    /// state after each of them.
+    fn hash_steps<F>(&self, inp: &[Fr], mut on_step: F) -> Result<Fr, PoseidonError>
+    where
+        F: FnMut(usize, RoundType, TraceStep, &Vec<Fr>),
+    {
+        self.check_inputs_len(inp.len())?;
+        let t = inp.len() + 1;
+        let n_rounds_f = self.constants.n_rounds_f;
+        let n_rounds_p = self.constants.n_rounds_p[t - 2];
 
-        for i in 0..(n_rounds_f + n_rounds_p) {
+        let mut state = vec![Fr::ZERO; t];
+        state[1..].clone_from_slice(inp);
+        for (i, round_type) in self.rounds(t) {
             self.ark(&mut state, &self.constants.c[t - 2], i * t);
+            on_step(i, round_type, TraceStep::Ark, &state);
             self.sbox(n_rounds_f, n_rounds_p, &mut state, i);
+            on_step(i, round_type, TraceStep::Sbox, &state);
             state = self.mix(&state, &self.constants.m[t - 2]);
+            on_step(i, round_type, TraceStep::Mix, &state);
         }
-
         Ok(state[0])
     }
+
+    /// Full permutation of a state of width `t = state.len()`, the same rounds as
+    /// `ark`, `sbox` and `mix` but without allocating. `scratch` must have the same length.
+    fn permute_state(&self, state: &mut [Fr], scratch: &mut [Fr]) {
+        let t = state.len();
+        let c = &self.constants.c[t - 2];
+        let m = &self.constants.m[t - 2];
+
+        for (i, round_type) in self.rounds(t) {
+            for (s, c) in state.iter_mut().zip(c[i * t..].iter()) {
+                s.add_assign(c);
+            }
+            if round_type == RoundType::Full {
+                state.iter_mut().for_each(pow5);
+            } else {
+                pow5(&mut state[0]);
+            }
+            for (new, row) in scratch.iter_mut().zip(m.iter()) {
+                *new = row
+                    .iter()
+                    .zip(state.iter())
+                    .fold(Fr::ZERO, |acc, (mij, s)| acc + *mij * s);
+            }
+            state.copy_from_slice(scratch);
+        }
+    }
+
+    /// Multi-lane version of `permute_state`, over a state of width `t = state.len()`.
+    fn permute_lanes<const L: usize>(&self, state: &mut [[Fr; L]], scratch: &mut [[Fr; L]]) {
+        let t = state.len();
+        let c = &self.constants.c[t - 2];
+        let m = &self.constants.m[t - 2];
+
+        for (i, round_type) in self.rounds(t) {
+            for (lanes, c) in state.iter_mut().zip(c[i * t..].iter()) {
+                for s in lanes.iter_mut() {
+                    s.add_assign(c);
+                }
+            }
+            if round_type == RoundType::Full {
+                state.iter_mut().flatten().for_each(pow5);
+            } else {
+                state[0].iter_mut().for_each(pow5);
+            }
+            for (new, row) in scratch.iter_mut().zip(m.iter()) {
+                *new = [Fr::ZERO; L];
+                for (mij, lanes) in row.iter().zip(state.iter()) {
+                    for (acc, s) in new.iter_mut().zip(lanes.iter()) {
+                        *acc += *mij * s;
+                    }
+                }
+            }
+            state.copy_from_slice(scratch);
+        }
+    }
+}
+
+/// Full rounds are split evenly before and after the partial rounds.
+fn round_type(n_rounds_f: usize, n_rounds_p: usize, i: usize) -> RoundType {
+    if i < n_rounds_f / 2 || i >= n_rounds_f / 2 + n_rounds_p {
+        RoundType::Full
+    } else {
+        RoundType::Partial
+    }
+}
+
+/// Loads one input per lane into a structure-of-arrays state, with a zero capacity element.
+fn load_lanes<const N: usize, const L: usize>(state: &mut [[Fr; L]], inputs: &[[Fr; N]]) {
+    state[0] = [Fr::ZERO; L];
+    for (lane, input) in inputs.iter().enumerate() {
+        for (i, x) in input.iter().enumerate() {
+            state[i + 1][lane] = *x;
+        }
+    }
+}
+
+/// Gauss-Jordan inversion, `None` if the matrix is singular.
+fn invert_matrix(m: &[Vec<Fr>]) -> Option<Vec<Vec<Fr>>> {
+    let n = m.len();
+    let mut a = m.to_vec();
+    let mut inv: Vec<Vec<Fr>> = (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| if i == j { Fr::ONE } else { Fr::ZERO })
+                .collect()
+        })
+        .collect();
+    for col in 0..n {
+        let pivot = (col..n).find(|row| !bool::from(a[*row][col].is_zero()))?;
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+        let pivot_inv = a[col][col].invert().unwrap();
+        for j in 0..n {
+            a[col][j] *= pivot_inv;
+            inv[col][j] *= pivot_inv;
+        }
+        for row in 0..n {
+            if row != col && !bool::from(a[row][col].is_zero()) {
+                let factor = a[row][col];
+                for j in 0..n {
+                    let (a_col, inv_col) = (a[col][j], inv[col][j]);
+                    a[row][j] -= factor * a_col;
+                    inv[row][j] -= factor * inv_col;
+                }
+            }
+        }
+    }
+    Some(inv)
+}
+
+fn pow5(x: &mut Fr) {
+    let aux = *x;
+    *x = x.square();
+    *x = x.square();
+    x.mul_assign(&aux);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fr_to_decimal;
+
+    #[test]
+    fn test_hash_lanes() {
+        let poseidon = Poseidon::new();
+        let inputs: [[Fr; 2]; 3] = [
+            [Fr::from(1), Fr::from(2)],
+            [Fr::from(3), Fr::from(4)],
+            [-Fr::ONE, Fr::ZERO],
+        ];
+        let hashes = poseidon.hash_lanes(&inputs).unwrap();
+        for (input, hash) in inputs.iter().zip(hashes.iter()) {
+            assert_eq!(*hash, poseidon.hash(input.to_vec()).unwrap());
+        }
+
+        let inputs: [[Fr; 16]; 8] =
+            std::array::from_fn(|lane| std::array::from_fn(|i| Fr::from((lane * 16 + i) as u64)));
+        let hashes = poseidon.hash_lanes(&inputs).unwrap();
+        for (input, hash) in inputs.iter().zip(hashes.iter()) {
+            assert_eq!(*hash, poseidon.hash(input.to_vec()).unwrap());
+        }
+
+        poseidon
+            .hash_lanes(&[[Fr::ONE; 17]; 2])
+            .expect_err("Wrong inputs length");
+    }
+
+    #[test]
+    fn test_permute_round_trip() {
+        let poseidon = Poseidon::new();
+        for t in 2..=17 {
+            let original: Vec<Fr> = (0..t).map(|i| Fr::from(i as u64 * 7 + t as u64)).collect();
+            let mut state = original.clone();
+            poseidon.permute(&mut state).unwrap();
+            assert_ne!(state, original);
+            poseidon.inverse_permute(&mut state).unwrap();
+            assert_eq!(state, original);
+
+            poseidon.inverse_permute(&mut state).unwrap();
+            poseidon.permute(&mut state).unwrap();
+            assert_eq!(state, original);
+        }
+
+        let mut state = vec![Fr::ZERO, Fr::from(1), Fr::from(2)];
+        poseidon.permute(&mut state).unwrap();
+        assert_eq!(
+            state[0],
+            poseidon.hash(vec![Fr::from(1), Fr::from(2)]).unwrap()
+        );
+
+        let x = Fr::from(12345);
+        assert_eq!(x.pow_vartime([5]).pow_vartime(INV_ALPHA), x);
+
+        poseidon
+            .permute(&mut [Fr::ZERO])
+            .expect_err("Wrong state width");
+        poseidon
+            .inverse_permute(&mut [Fr::ZERO; 18])
+            .expect_err("Wrong state width");
+    }
+
+    #[test]
+    fn test_hash_with_trace() {
+        let poseidon = Poseidon::new();
+        let inputs = vec![Fr::from(1), Fr::from(2)];
+        let trace = poseidon.hash_with_trace(inputs.clone()).unwrap();
+        assert_eq!(trace.output, poseidon.hash(inputs).unwrap());
+        assert_eq!(
+            trace.initial_state,
+            vec![Fr::ZERO, Fr::from(1), Fr::from(2)]
+        );
+        assert_eq!(trace.rows.len(), 3 * (8 + 57));
+        assert_eq!(trace.rows.last().unwrap().state[0], trace.output);
+
+        let round_types: Vec<RoundType> = trace.rows.iter().map(|row| row.round_type).collect();
+        assert_eq!(round_types[3 * 3], RoundType::Full);
+        assert_eq!(round_types[3 * 4], RoundType::Partial);
+        assert_eq!(round_types[3 * 60], RoundType::Partial);
+        assert_eq!(round_types[3 * 61], RoundType::Full);
+
+        let json: serde_json::Value = serde_json::from_str(&trace.to_json()).unwrap();
+        assert_eq!(json["inputs"], serde_json::json!(["1", "2"]));
+        assert_eq!(
+            json["rows"][3 * 4 + 1],
+            serde_json::json!({
+                "round": 4,
+                "round_type": "partial",
+                "step": "sbox",
+                "state": trace.rows[3 * 4 + 1]
+                    .state
+                    .iter()
+                    .map(fr_to_decimal)
+                    .collect::<Vec<_>>(),
+            })
+        );
+        assert_eq!(
+            json["output"],
+            serde_json::json!(fr_to_decimal(&trace.output))
+        );
+
+        let csv = trace.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), trace.rows.len() + 1);
+        assert_eq!(lines[0], "round,round_type,step,state_0,state_1,state_2");
+        assert_eq!(
+            lines[1],
+            format!(
+                "0,full,ark,{}",
+                trace.rows[0]
+                    .state
+                    .iter()
+                    .map(fr_to_decimal)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )
+        );
+        assert_eq!(lines[1].split(',').count(), 6);
+    }
 }
@@ -0,0 +1,113 @@
+use crate::sponge::{domain_tag, PoseidonSponge};
+use crate::utils::bytes_to_fields;
+use crate::Fr;
+use halo2curves::ff::*;
+use rand_core::{impls, CryptoRng, Error, RngCore, SeedableRng};
+
+/// Domain of [`PoseidonRng`], packed into the sponge capacity with [`domain_tag`].
+pub const RNG_DOMAIN: &[u8] = b"poseidon-rs/rng";
+
+/// Rate of the RNG sponge, i.e. a width 3 permutation.
+const RATE: usize = 2;
+
+/// Bytes output per squeezed element. Only the low 128 bits are used,
+/// as the full 254-bit representation of a uniform `Fr` is not uniform over bytes.
+const BYTES_PER_ELEMENT: usize = 16;
+
+/// Deterministic RNG squeezing a Poseidon sponge that absorbed a seed of field elements,
+/// so that its [`PoseidonRng::next_fr`] outputs can be re-derived in a circuit.
+#[derive(Debug, Clone)]
+pub struct PoseidonRng {
+    sponge: PoseidonSponge,
+    bytes: Vec<u8>,
+}
+
+impl PoseidonRng {
+    /// Absorbs the seed length, then the seed.
+    pub fn from_fields(seed: &[Fr]) -> PoseidonRng {
+        let mut sponge = PoseidonSponge::new(RATE, domain_tag(RNG_DOMAIN).unwrap()).unwrap();
+        sponge.absorb(&Fr::from(seed.len() as u64));
+        sponge.absorb_slice(seed);
+        PoseidonRng {
+            sponge,
+            bytes: Vec::new(),
+        }
+    }
+
+    /// Next squeezed element, uniform over `Fr`.
+    pub fn next_fr(&mut self) -> Fr {
+        self.sponge.squeeze()
+    }
+}
+
+impl RngCore for PoseidonRng {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for byte in dest.iter_mut() {
+            if self.bytes.is_empty() {
+                let element = self.next_fr();
+                self.bytes = element.to_repr().as_ref()[..BYTES_PER_ELEMENT].to_vec();
+                self.bytes.reverse();
+            }
+            *byte = self.bytes.pop().unwrap();
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for PoseidonRng {
+    type Seed = [u8; 32];
+
+    /// Seeds with the 32 bytes packed into field elements, 31 bytes per element.
+    fn from_seed(seed: [u8; 32]) -> PoseidonRng {
+        PoseidonRng::from_fields(&bytes_to_fields(&seed))
+    }
+}
+
+impl CryptoRng for PoseidonRng {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let seed = [Fr::from(1), Fr::from(2)];
+        let mut rng = PoseidonRng::from_fields(&seed);
+        let first = rng.next_fr();
+        assert_ne!(first, rng.next_fr());
+        assert_eq!(PoseidonRng::from_fields(&seed).next_fr(), first);
+        assert_ne!(PoseidonRng::from_fields(&seed[..1]).next_fr(), first);
+        assert_ne!(
+            PoseidonRng::from_fields(&[Fr::from(1), Fr::from(2), Fr::ZERO]).next_fr(),
+            first
+        );
+
+        // Bytes are the low 16 bytes of each element, little-endian.
+        let mut bytes = [0u8; 20];
+        PoseidonRng::from_fields(&seed).fill_bytes(&mut bytes);
+        let mut rng = PoseidonRng::from_fields(&seed);
+        let expected = [
+            &rng.next_fr().to_repr().as_ref()[..16],
+            &rng.next_fr().to_repr().as_ref()[..4],
+        ]
+        .concat();
+        assert_eq!(bytes.to_vec(), expected);
+
+        let mut rng = PoseidonRng::from_seed([3; 32]);
+        let random = Fr::random(&mut rng);
+        assert_eq!(Fr::random(&mut PoseidonRng::from_seed([3; 32])), random);
+        assert_ne!(Fr::random(&mut rng), random);
+    }
+}
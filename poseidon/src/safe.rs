@@ -0,0 +1,274 @@
+use crate::sponge::PoseidonSponge;
+use crate::{Fr, PoseidonError};
+use halo2curves::ff::*;
+use sha3::{Digest, Sha3_256};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SafeError {
+    #[error("Invalid IO pattern: {0}")]
+    InvalidPattern(&'static str),
+    #[error("Call deviates from the IO pattern: expected `{expected}` but got `{got}`")]
+    UnexpectedCall { expected: String, got: String },
+    #[error("Call length `{0}` exceeds the maximum operation length")]
+    CallTooLong(usize),
+    #[error("IO pattern not finished: `{0}` operations left")]
+    PatternNotFinished(usize),
+    #[error("Sponge aborted after a previous error")]
+    Aborted,
+    #[error(transparent)]
+    Poseidon(#[from] PoseidonError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoOp {
+    Absorb(u32),
+    Squeeze(u32),
+}
+
+impl IoOp {
+    fn len(&self) -> u32 {
+        match self {
+            IoOp::Absorb(len) | IoOp::Squeeze(len) => *len,
+        }
+    }
+
+    fn with_len(&self, len: u32) -> IoOp {
+        match self {
+            IoOp::Absorb(_) => IoOp::Absorb(len),
+            IoOp::Squeeze(_) => IoOp::Squeeze(len),
+        }
+    }
+
+    fn same_kind(&self, other: &IoOp) -> bool {
+        matches!(
+            (self, other),
+            (IoOp::Absorb(_), IoOp::Absorb(_)) | (IoOp::Squeeze(_), IoOp::Squeeze(_))
+        )
+    }
+
+    /// 32-bit word of the SAFE encoding: the MSB is set for absorb, the rest is the length.
+    fn encode(&self) -> u32 {
+        match self {
+            IoOp::Absorb(len) => 0x8000_0000 | len,
+            IoOp::Squeeze(len) => *len,
+        }
+    }
+}
+
+impl std::fmt::Display for IoOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IoOp::Absorb(len) => write!(f, "ABSORB({})", len),
+            IoOp::Squeeze(len) => write!(f, "SQUEEZE({})", len),
+        }
+    }
+}
+
+/// Sequence of operations a [`SafeSponge`] is allowed to perform.
+/// Consecutive operations of the same kind are merged, as in the SAFE tag computation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IoPattern(Vec<IoOp>);
+
+impl IoPattern {
+    pub fn new(ops: &[IoOp]) -> Result<IoPattern, SafeError> {
+        let mut merged: Vec<IoOp> = Vec::new();
+        for op in ops {
+            if op.len() == 0 {
+                return Err(SafeError::InvalidPattern("zero length operation"));
+            }
+            if op.len() >= 0x8000_0000 {
+                return Err(SafeError::InvalidPattern(
+                    "operation length exceeds 2^31 - 1",
+                ));
+            }
+            match merged.last_mut() {
+                Some(last) if last.same_kind(op) => {
+                    let len = last
+                        .len()
+                        .checked_add(op.len())
+                        .filter(|len| *len < 0x8000_0000)
+                        .ok_or(SafeError::InvalidPattern(
+                            "operation length exceeds 2^31 - 1",
+                        ))?;
+                    *last = last.with_len(len);
+                }
+                _ => merged.push(*op),
+            }
+        }
+        Ok(IoPattern(merged))
+    }
+
+    pub fn ops(&self) -> &[IoOp] {
+        &self.0
+    }
+
+    /// First 128 bits of SHA3-256 over the big-endian encoded operations followed by
+    /// `domain_separator`, read as a big-endian integer.
+    pub fn tag(&self, domain_separator: &[u8]) -> Fr {
+        let mut hasher = Sha3_256::new();
+        for op in self.0.iter() {
+            hasher.update(op.encode().to_be_bytes());
+        }
+        hasher.update(domain_separator);
+        let digest = hasher.finalize();
+        let mut tag = [0u8; 16];
+        tag.copy_from_slice(&digest[..16]);
+        Fr::from_u128(u128::from_be_bytes(tag))
+    }
+}
+
+/// SAFE sponge: a duplex sponge whose capacity is initialized with the tag of its IO pattern
+/// and domain separator, and whose calls are checked against that pattern.
+/// Any deviation erases the state, and every later call fails.
+#[derive(Debug)]
+pub struct SafeSponge {
+    sponge: Option<PoseidonSponge>,
+    pattern: IoPattern,
+    op: usize,
+    done: u32,
+}
+
+impl SafeSponge {
+    pub fn new(
+        pattern: IoPattern,
+        domain_separator: &[u8],
+        rate: usize,
+    ) -> Result<SafeSponge, SafeError> {
+        let sponge = PoseidonSponge::new(rate, pattern.tag(domain_separator))?;
+        Ok(SafeSponge {
+            sponge: Some(sponge),
+            pattern,
+            op: 0,
+            done: 0,
+        })
+    }
+
+    pub fn absorb(&mut self, values: &[Fr]) -> Result<(), SafeError> {
+        let len = self.call_len(values.len())?;
+        self.advance(IoOp::Absorb(len))?.absorb_slice(values);
+        Ok(())
+    }
+
+    pub fn squeeze(&mut self, length: usize) -> Result<Vec<Fr>, SafeError> {
+        let len = self.call_len(length)?;
+        let sponge = self.advance(IoOp::Squeeze(len))?;
+        Ok((0..length).map(|_| sponge.squeeze()).collect())
+    }
+
+    /// Checks that the whole IO pattern was performed.
+    pub fn finish(mut self) -> Result<(), SafeError> {
+        if self.sponge.take().is_none() {
+            return Err(SafeError::Aborted);
+        }
+        let left = self.pattern.0.len() - self.op;
+        if left > 0 {
+            return Err(SafeError::PatternNotFinished(left));
+        }
+        Ok(())
+    }
+
+    /// Length of a call as an operation length, aborting the sponge if it does not fit.
+    fn call_len(&mut self, len: usize) -> Result<u32, SafeError> {
+        u32::try_from(len).map_err(|_| {
+            self.sponge = None;
+            SafeError::CallTooLong(len)
+        })
+    }
+
+    /// Consumes `call` from the IO pattern, a call may cover part of an operation.
+    fn advance(&mut self, call: IoOp) -> Result<&mut PoseidonSponge, SafeError> {
+        if self.sponge.is_none() {
+            return Err(SafeError::Aborted);
+        }
+        let expected = self
+            .pattern
+            .0
+            .get(self.op)
+            .map(|op| op.with_len(op.len() - self.done));
+        match expected {
+            Some(op) if op.same_kind(&call) && call.len() > 0 && call.len() <= op.len() => {
+                self.done += call.len();
+                if self.done == self.pattern.0[self.op].len() {
+                    self.op += 1;
+                    self.done = 0;
+                }
+                Ok(self.sponge.as_mut().unwrap())
+            }
+            _ => {
+                self.sponge = None;
+                Err(SafeError::UnexpectedCall {
+                    expected: expected.map_or("end".to_string(), |op| op.to_string()),
+                    got: call.to_string(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let merged = IoPattern::new(&[IoOp::Absorb(3), IoOp::Squeeze(1)]).unwrap();
+        let split = IoPattern::new(&[IoOp::Absorb(1), IoOp::Absorb(2), IoOp::Squeeze(1)]).unwrap();
+        assert_eq!(merged, split);
+        assert_eq!(merged.tag(b"domain"), split.tag(b"domain"));
+        assert_ne!(merged.tag(b"domain"), merged.tag(b"other"));
+        let other = IoPattern::new(&[IoOp::Absorb(3), IoOp::Squeeze(2)]).unwrap();
+        assert_ne!(merged.tag(b"domain"), other.tag(b"domain"));
+        IoPattern::new(&[IoOp::Absorb(0)]).expect_err("Zero length");
+        IoPattern::new(&[IoOp::Absorb(0x8000_0000)]).expect_err("Too long");
+    }
+
+    #[test]
+    fn test_safe_sponge() {
+        let pattern = IoPattern::new(&[IoOp::Absorb(3), IoOp::Squeeze(2)]).unwrap();
+        let values = [Fr::from(1), Fr::from(2), Fr::from(3)];
+
+        let mut safe = SafeSponge::new(pattern.clone(), b"test", 2).unwrap();
+        safe.absorb(&values[..1]).unwrap();
+        safe.absorb(&values[1..]).unwrap();
+        let output = safe.squeeze(2).unwrap();
+        safe.finish().unwrap();
+
+        let mut sponge = PoseidonSponge::new(2, pattern.tag(b"test")).unwrap();
+        sponge.absorb_slice(&values);
+        assert_eq!(output, vec![sponge.squeeze(), sponge.squeeze()]);
+
+        let mut safe = SafeSponge::new(pattern.clone(), b"test", 2).unwrap();
+        safe.squeeze(1).expect_err("Squeeze before absorb");
+        assert!(matches!(safe.absorb(&values), Err(SafeError::Aborted)));
+
+        let mut safe = SafeSponge::new(pattern.clone(), b"test", 2).unwrap();
+        safe.absorb(&[values, values].concat())
+            .expect_err("Absorb too many");
+
+        let mut safe = SafeSponge::new(pattern.clone(), b"test", 2).unwrap();
+        safe.absorb(&[]).expect_err("Empty absorb");
+        assert!(matches!(safe.absorb(&values), Err(SafeError::Aborted)));
+
+        // A squeeze of 2^32 + 2 must not pass as a squeeze of 2.
+        #[cfg(target_pointer_width = "64")]
+        {
+            let mut safe = SafeSponge::new(pattern.clone(), b"test", 2).unwrap();
+            safe.absorb(&values).unwrap();
+            assert!(matches!(
+                safe.squeeze((1 << 32) + 2),
+                Err(SafeError::CallTooLong(_))
+            ));
+            assert!(matches!(safe.squeeze(2), Err(SafeError::Aborted)));
+        }
+
+        let mut safe = SafeSponge::new(pattern, b"test", 2).unwrap();
+        safe.absorb(&values).unwrap();
+        safe.squeeze(1).unwrap();
+        assert!(matches!(
+            safe.finish(),
+            Err(SafeError::PatternNotFinished(1))
+        ));
+    }
+}
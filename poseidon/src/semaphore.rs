@@ -0,0 +1,173 @@
+//! Identity helpers of the Semaphore protocol, v3 and v4.
+
+use crate::babyjubjub::Point;
+use crate::eddsa::PrivateKey;
+use crate::{poseidon_fields, Fr};
+use halo2curves::ff::*;
+use sha3::{Digest, Keccak256};
+
+/// Semaphore `hash`: Keccak-256 of a 32-byte big-endian value shifted right by 8 bits
+/// to fit the field. Applied to scopes (external nullifiers) and messages.
+pub fn hash_to_field(value: &[u8; 32]) -> Fr {
+    let digest = Keccak256::digest(value);
+    let mut repr = <Fr as PrimeField>::Repr::default();
+    for (byte, digest_byte) in repr.as_mut().iter_mut().zip(digest[..31].iter().rev()) {
+        *byte = *digest_byte;
+    }
+    Fr::from_repr(repr).unwrap()
+}
+
+/// ethers `encodeBytes32String`: UTF-8 bytes right-padded with zeros, `None` above 31 bytes.
+pub fn encode_bytes32_string(text: &str) -> Option<[u8; 32]> {
+    if text.len() > 31 {
+        return None;
+    }
+    let mut bytes = [0u8; 32];
+    bytes[..text.len()].copy_from_slice(text.as_bytes());
+    Some(bytes)
+}
+
+/// Semaphore v3 identity: `secret = Poseidon(nullifier, trapdoor)`, `commitment = Poseidon(secret)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityV3 {
+    pub trapdoor: Fr,
+    pub nullifier: Fr,
+}
+
+impl IdentityV3 {
+    pub fn new(trapdoor: Fr, nullifier: Fr) -> IdentityV3 {
+        IdentityV3 {
+            trapdoor,
+            nullifier,
+        }
+    }
+
+    pub fn secret(&self) -> Fr {
+        poseidon_fields(&[self.nullifier, self.trapdoor]).unwrap()
+    }
+
+    pub fn commitment(&self) -> Fr {
+        poseidon_fields(&[self.secret()]).unwrap()
+    }
+
+    /// `Poseidon(external_nullifier, nullifier)`, with `external_nullifier` already hashed
+    /// with [`hash_to_field`].
+    pub fn nullifier_hash(&self, external_nullifier: &Fr) -> Fr {
+        poseidon_fields(&[*external_nullifier, self.nullifier]).unwrap()
+    }
+}
+
+/// Semaphore v4 identity: a Baby Jubjub EdDSA key with `commitment = Poseidon(A.x, A.y)`.
+/// `new Identity(privateKey)` of `@semaphore-protocol/identity` with a string key is
+/// `IdentityV4::from_secret(privateKey)`; without a key it draws 32 random bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityV4 {
+    private_key: PrivateKey,
+}
+
+impl IdentityV4 {
+    pub fn new(private_key: PrivateKey) -> IdentityV4 {
+        IdentityV4 { private_key }
+    }
+
+    /// Identity whose private key is the UTF-8 bytes of `secret`.
+    pub fn from_secret(secret: &str) -> IdentityV4 {
+        IdentityV4::new(PrivateKey::from_bytes(secret.as_bytes()))
+    }
+
+    pub fn private_key(&self) -> &PrivateKey {
+        &self.private_key
+    }
+
+    /// `deriveSecretScalar` of zk-kit, the `secret` input of the circuit.
+    pub fn secret_scalar(&self) -> Fr {
+        let mut repr = <Fr as PrimeField>::Repr::default();
+        let bytes = self.private_key.scalar_key().to_bytes_le();
+        repr.as_mut()[..bytes.len()].copy_from_slice(&bytes);
+        // The scalar is below the subgroup order, hence below the field modulus.
+        Fr::from_repr(repr).unwrap()
+    }
+
+    pub fn public_key(&self) -> Point {
+        self.private_key.public()
+    }
+
+    pub fn commitment(&self) -> Fr {
+        let public_key = self.public_key();
+        poseidon_fields(&[public_key.x(), public_key.y()]).unwrap()
+    }
+
+    /// `Poseidon(scope, secret_scalar)`, with `scope` already hashed with [`hash_to_field`].
+    pub fn nullifier(&self, scope: &Fr) -> Fr {
+        poseidon_fields(&[*scope, self.secret_scalar()]).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::babyjubjub::base8;
+    use num_bigint::BigUint;
+
+    #[test]
+    fn test_hash_to_field() {
+        // keccak256(bytes32(0)) = 0x290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563
+        assert_eq!(
+            hash_to_field(&[0; 32]),
+            Fr::from_str_vartime(
+                "72536837793382353857766664600029564596379343648020771091641483823512744933"
+            )
+            .unwrap()
+        );
+        assert_eq!(encode_bytes32_string("scope").unwrap()[..6], *b"scope\0");
+        assert_eq!(encode_bytes32_string(&"a".repeat(32)), None);
+    }
+
+    #[test]
+    fn test_identity_v3() {
+        let identity = IdentityV3::new(Fr::from(1), Fr::from(2));
+        let secret = poseidon_fields(&[Fr::from(2), Fr::from(1)]).unwrap();
+        assert_eq!(identity.secret(), secret);
+        assert_eq!(identity.commitment(), poseidon_fields(&[secret]).unwrap());
+        let external_nullifier = hash_to_field(&encode_bytes32_string("scope").unwrap());
+        assert_eq!(
+            identity.nullifier_hash(&external_nullifier),
+            poseidon_fields(&[external_nullifier, Fr::from(2)]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_identity_v4() {
+        let identity = IdentityV4::new(PrivateKey::new([1; 32]));
+        let secret = identity.secret_scalar();
+        let public_key = identity.public_key();
+        assert_eq!(
+            base8().mul_scalar(&BigUint::from_bytes_le(secret.to_repr().as_ref())),
+            public_key
+        );
+        assert_eq!(
+            identity.commitment(),
+            poseidon_fields(&[public_key.x(), public_key.y()]).unwrap()
+        );
+        let scope = Fr::from(32);
+        assert_eq!(
+            identity.nullifier(&scope),
+            poseidon_fields(&[scope, secret]).unwrap()
+        );
+        assert_ne!(
+            identity.nullifier(&scope),
+            IdentityV4::new(PrivateKey::new([2; 32])).nullifier(&scope)
+        );
+
+        // String keys of any length are hashed whole.
+        assert_eq!(IdentityV4::new(PrivateKey::from_bytes(&[1; 32])), identity);
+        let secret = IdentityV4::from_secret("secret");
+        assert_eq!(secret.private_key().as_bytes(), b"secret");
+        assert_ne!(secret.commitment(), identity.commitment());
+        assert_ne!(
+            secret.commitment(),
+            IdentityV4::from_secret("secret\0").commitment()
+        );
+        assert!(secret.public_key().in_sub_group());
+    }
+}
@@ -0,0 +1,530 @@
+//! Serde serializer encoding any `Serialize` value as field elements, so that existing
+//! types can be hashed without a [`ToFieldElements`] implementation.
+//!
+//! The encoding agrees with [`ToFieldElements`] wherever both apply:
+//! - integers, `bool` and `char` take one element, negative integers are negated in the field;
+//! - strings and byte buffers are their length followed by the bytes packed 31 per element;
+//! - sequences are their element count followed by the elements;
+//! - maps are their entry count followed by the entries (key then value), sorted by the
+//!   encoding of the key, so that the iteration order of e.g. a `HashMap` does not matter;
+//! - tuples, structs and their fields are concatenated without counts or names;
+//! - `None` and `()` are `0`, and `Some(value)` is `1` followed by `value`;
+//! - enum variants are their index followed by the variant content.
+//!
+//! `Vec<u8>` serializes as a sequence, one element per byte, unless marked as bytes
+//! (e.g. with `serde_bytes`), and `[u8; N]` as a tuple of `N` elements, where
+//! [`ToFieldElements`] packs both 31 bytes per element. Floating point numbers are rejected.
+//!
+//! No type information is encoded, so the encoding is injective only among values of the
+//! same Rust type: `true` and `1u8`, or `"a"` and `vec![97u8]`, have the same encoding.
+//! Self-describing values such as `serde_json::Value` mix types within one type and do
+//! collide; hash JSON documents with [`json_hash`](crate::json_hash) instead.
+
+use crate::hashable::{hash_encoding, ToFieldElements};
+use crate::Fr;
+use halo2curves::ff::*;
+use serde::ser::{self, Serialize};
+use std::cmp::Ordering;
+use std::fmt::Display;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SerializeError {
+    #[error("Floating point values have no field encoding")]
+    Float,
+    #[error("{0}")]
+    Custom(String),
+}
+
+impl ser::Error for SerializeError {
+    fn custom<T: Display>(msg: T) -> Self {
+        SerializeError::Custom(msg.to_string())
+    }
+}
+
+/// Encodes `value` as field elements.
+pub fn to_fields<T: Serialize + ?Sized>(value: &T) -> Result<Vec<Fr>, SerializeError> {
+    let mut serializer = FieldSerializer::default();
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_fields())
+}
+
+/// Hashes the encoding of `value` the same way as [`hash_object`](crate::hashable::hash_object).
+pub fn hash<T: Serialize + ?Sized>(value: &T) -> Result<Fr, SerializeError> {
+    Ok(hash_encoding(&to_fields(value)?))
+}
+
+/// Serializer appending the encoding of each serialized value to its output.
+#[derive(Debug, Default)]
+pub struct FieldSerializer {
+    out: Vec<Fr>,
+}
+
+impl FieldSerializer {
+    pub fn into_fields(self) -> Vec<Fr> {
+        self.out
+    }
+
+    fn push<T: ToFieldElements + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        value.append_fields(&mut self.out);
+        Ok(())
+    }
+
+    fn variant(&mut self, index: u32) -> Result<(), SerializeError> {
+        self.push(&index)
+    }
+
+    /// Starts a compound value, reserving an element for its count if it has one.
+    fn compound(&mut self, counted: bool) -> Compound<'_> {
+        let count_at = if counted {
+            self.out.push(Fr::ZERO);
+            Some(self.out.len() - 1)
+        } else {
+            None
+        };
+        Compound {
+            ser: self,
+            count_at,
+            count: 0,
+        }
+    }
+}
+
+/// Sequence, map, tuple or struct being serialized. Counts are written on `end`, so
+/// sequences of unknown length are supported.
+pub struct Compound<'a> {
+    ser: &'a mut FieldSerializer,
+    count_at: Option<usize>,
+    count: u64,
+}
+
+impl<'a> Compound<'a> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.count += 1;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn finish(self) -> Result<(), SerializeError> {
+        if let Some(at) = self.count_at {
+            self.ser.out[at] = Fr::from(self.count);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ser::Serializer for &'a mut FieldSerializer {
+    type Ok = ();
+    type Error = SerializeError;
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = MapCompound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), SerializeError> {
+        self.push(&v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), SerializeError> {
+        self.push(&v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), SerializeError> {
+        self.push(&v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), SerializeError> {
+        self.push(&v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), SerializeError> {
+        self.push(&v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), SerializeError> {
+        self.push(&v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), SerializeError> {
+        self.push(&(v as u16))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), SerializeError> {
+        self.push(&v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), SerializeError> {
+        self.push(&v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), SerializeError> {
+        self.push(&v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), SerializeError> {
+        self.push(&v)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<(), SerializeError> {
+        Err(SerializeError::Float)
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<(), SerializeError> {
+        Err(SerializeError::Float)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), SerializeError> {
+        self.push(&(v as u32))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), SerializeError> {
+        self.push(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), SerializeError> {
+        self.push(v)
+    }
+
+    fn serialize_none(self) -> Result<(), SerializeError> {
+        self.push(&false)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), SerializeError> {
+        self.push(&true)?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), SerializeError> {
+        self.push(&false)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), SerializeError> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), SerializeError> {
+        self.variant(variant_index)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), SerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), SerializeError> {
+        self.variant(variant_index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a>, SerializeError> {
+        Ok(self.compound(true))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Compound<'a>, SerializeError> {
+        Ok(self.compound(false))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, SerializeError> {
+        Ok(self.compound(false))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, SerializeError> {
+        self.variant(variant_index)?;
+        Ok(self.compound(false))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapCompound<'a>, SerializeError> {
+        Ok(MapCompound {
+            ser: self,
+            entries: Vec::new(),
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, SerializeError> {
+        Ok(self.compound(false))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, SerializeError> {
+        self.variant(variant_index)?;
+        Ok(self.compound(false))
+    }
+}
+
+impl<'a> ser::SerializeSeq for Compound<'a> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), SerializeError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), SerializeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTuple for Compound<'a> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), SerializeError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), SerializeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for Compound<'a> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), SerializeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for Compound<'a> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), SerializeError> {
+        self.finish()
+    }
+}
+
+/// Map being serialized. Entries are encoded separately and written on `end`, sorted by
+/// the encoding of their key.
+pub struct MapCompound<'a> {
+    ser: &'a mut FieldSerializer,
+    entries: Vec<(Vec<Fr>, Vec<Fr>)>,
+}
+
+impl<'a> ser::SerializeMap for MapCompound<'a> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerializeError> {
+        self.entries.push((to_fields(key)?, Vec::new()));
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        let (_, encoded) = self
+            .entries
+            .last_mut()
+            .ok_or_else(|| ser::Error::custom("map value without a key"))?;
+        *encoded = to_fields(value)?;
+        Ok(())
+    }
+
+    fn end(mut self) -> Result<(), SerializeError> {
+        self.entries.sort_by(|(a, _), (b, _)| encoding_order(a, b));
+        self.ser.push(&(self.entries.len() as u64))?;
+        for (key, value) in self.entries {
+            self.ser.out.extend(key);
+            self.ser.out.extend(value);
+        }
+        Ok(())
+    }
+}
+
+/// Lexicographic order of encodings, comparing elements by their byte representation.
+fn encoding_order(a: &[Fr], b: &[Fr]) -> Ordering {
+    for (x, y) in a.iter().zip(b.iter()) {
+        match x.to_repr().as_ref().cmp(y.to_repr().as_ref()) {
+            Ordering::Equal => {}
+            order => return order,
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+impl<'a> ser::SerializeStruct for Compound<'a> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), SerializeError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), SerializeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for Compound<'a> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), SerializeError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), SerializeError> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashable::hash_object;
+    use serde::Serialize;
+    use std::collections::{BTreeMap, HashMap};
+
+    #[derive(Serialize)]
+    struct Credential {
+        subject: String,
+        age: u32,
+        scores: Vec<i64>,
+        expires: Option<u64>,
+        kind: Kind,
+    }
+
+    #[derive(Serialize)]
+    enum Kind {
+        Basic,
+        Scoped { scope: u64 },
+    }
+
+    #[test]
+    fn test_matches_to_field_elements() {
+        let value = (7u64, "ab".to_string(), vec![1u32, 2], -3i32, true);
+        assert_eq!(to_fields(&value).unwrap(), value.to_fields());
+        assert_eq!(hash(&value).unwrap(), hash_object(&value));
+        assert_eq!(to_fields("").unwrap(), vec![Fr::ZERO]);
+    }
+
+    #[test]
+    fn test_struct_encoding() {
+        let credential = Credential {
+            subject: "alice".to_string(),
+            age: 30,
+            scores: vec![-1, 2],
+            expires: Some(1700000000),
+            kind: Kind::Scoped { scope: 9 },
+        };
+        let mut expected = "alice".to_fields();
+        expected.push(Fr::from(30));
+        expected.extend(vec![-1i64, 2].to_fields());
+        expected.extend([Fr::ONE, Fr::from(1700000000)]);
+        expected.extend([Fr::ONE, Fr::from(9)]);
+        assert_eq!(to_fields(&credential).unwrap(), expected);
+
+        let basic = Credential {
+            expires: None,
+            kind: Kind::Basic,
+            ..credential
+        };
+        let fields = to_fields(&basic).unwrap();
+        assert_eq!(fields[fields.len() - 2..], [Fr::ZERO, Fr::ZERO]);
+    }
+
+    #[test]
+    fn test_maps_and_errors() {
+        let mut map = BTreeMap::new();
+        map.insert("a", 1u64);
+        map.insert("b", 2u64);
+        let mut expected = vec![Fr::from(2)];
+        expected.extend(("a", 1u64, "b", 2u64).to_fields());
+        assert_eq!(to_fields(&map).unwrap(), expected);
+
+        // Entries are sorted by their encoded key, whatever the iteration order.
+        let mut forward = HashMap::new();
+        let mut backward = HashMap::new();
+        for i in 0..64u64 {
+            forward.insert(i, i * i);
+            backward.insert(63 - i, (63 - i) * (63 - i));
+        }
+        assert_eq!(hash(&forward).unwrap(), hash(&backward).unwrap());
+        let sorted: BTreeMap<u64, u64> = forward.clone().into_iter().collect();
+        assert_eq!(to_fields(&forward).unwrap(), to_fields(&sorted).unwrap());
+
+        let json: serde_json::Value = serde_json::from_str(r#"{"x": [1, "y"]}"#).unwrap();
+        assert_eq!(
+            to_fields(&json).unwrap(),
+            [
+                vec![Fr::ONE],
+                "x".to_fields(),
+                vec![Fr::from(2), Fr::ONE],
+                "y".to_fields()
+            ]
+            .concat()
+        );
+        assert_eq!(to_fields(&1.5f64), Err(SerializeError::Float));
+    }
+
+    #[test]
+    fn test_untyped_values() {
+        let fields =
+            |json: &str| to_fields(&serde_json::from_str::<serde_json::Value>(json).unwrap());
+        assert_eq!(to_fields(&()).unwrap(), vec![Fr::ZERO]);
+        assert_ne!(fields("[null, 5]"), fields("[5, null]"));
+        assert_ne!(fields("[null]"), fields("[]"));
+
+        // Only the Rust type is typed: JSON scalars of different types may collide.
+        assert_eq!(fields("[true]"), fields("[1]"));
+        assert_eq!(fields(r#"["a"]"#), fields("[[97]]"));
+        assert_eq!(fields("[0.5]"), Err(SerializeError::Float));
+    }
+}
@@ -0,0 +1,40 @@
+//! Serde encoding of field elements as `0x`-prefixed big-endian hex strings,
+//! for use with `#[serde(with = "poseidon_rs::serde_fr")]`.
+
+use crate::utils::{fr_from_hex, fr_to_hex};
+use crate::Fr;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serializer};
+
+pub fn serialize<S: Serializer>(field: &Fr, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&fr_to_hex(field))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Fr, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    fr_from_hex(&hex).ok_or_else(|| D::Error::custom(format!("invalid field element {}", hex)))
+}
+
+/// Same encoding for a sequence of field elements, `#[serde(with = "poseidon_rs::serde_fr::vec")]`.
+pub mod vec {
+    use super::*;
+    use serde::ser::SerializeSeq;
+
+    pub fn serialize<S: Serializer>(fields: &[Fr], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(fields.len()))?;
+        for field in fields {
+            seq.serialize_element(&fr_to_hex(field))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Fr>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|hex| {
+                fr_from_hex(hex)
+                    .ok_or_else(|| D::Error::custom(format!("invalid field element {}", hex)))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,203 @@
+use crate::utils::bytes_to_fields;
+use crate::{poseidon_default, Fr, PoseidonError};
+use halo2curves::ff::*;
+
+/// Duplex sponge over the Poseidon permutation of width `rate + 1`.
+/// The first state element is the capacity, initialized with a domain separation value.
+#[derive(Debug, Clone)]
+pub struct PoseidonSponge {
+    state: Vec<Fr>,
+    absorb_pos: usize,
+    squeeze_pos: usize,
+}
+
+impl PoseidonSponge {
+    pub fn new(rate: usize, capacity_iv: Fr) -> Result<PoseidonSponge, PoseidonError> {
+        poseidon_default().check_width(rate + 1)?;
+        let mut state = vec![Fr::ZERO; rate + 1];
+        state[0] = capacity_iv;
+        Ok(PoseidonSponge {
+            state,
+            absorb_pos: 0,
+            squeeze_pos: rate,
+        })
+    }
+
+    pub fn rate(&self) -> usize {
+        self.state.len() - 1
+    }
+
+    /// Adds `value` into the next rate element, permuting first when the rate is full.
+    pub fn absorb(&mut self, value: &Fr) {
+        if self.absorb_pos == self.rate() {
+            self.permute();
+            self.absorb_pos = 0;
+        }
+        self.state[1 + self.absorb_pos] += value;
+        self.absorb_pos += 1;
+        self.squeeze_pos = self.rate();
+    }
+
+    pub fn absorb_slice(&mut self, values: &[Fr]) {
+        for value in values {
+            self.absorb(value);
+        }
+    }
+
+    /// Returns the next rate element, permuting first after absorbing or when the rate is exhausted.
+    pub fn squeeze(&mut self) -> Fr {
+        if self.squeeze_pos == self.rate() {
+            self.permute();
+            self.squeeze_pos = 0;
+            self.absorb_pos = 0;
+        }
+        let value = self.state[1 + self.squeeze_pos];
+        self.squeeze_pos += 1;
+        value
+    }
+
+    fn permute(&mut self) {
+        // The width was checked in `new`.
+        poseidon_default().permute(&mut self.state).unwrap();
+    }
+}
+
+/// Packs a domain name of at most 31 bytes little-endian into a field element,
+/// used as the capacity IV of a sponge to separate constructions.
+pub fn domain_tag(domain: &[u8]) -> Result<Fr, PoseidonError> {
+    if domain.len() > 31 {
+        return Err(PoseidonError::DomainTagTooLong(domain.len()));
+    }
+    Ok(bytes_to_fields(domain).pop().unwrap_or(Fr::ZERO))
+}
+
+/// Rate of the transcript sponge, i.e. a width 3 permutation.
+pub const TRANSCRIPT_RATE: usize = 2;
+
+/// Fiat-Shamir transcript over a Poseidon duplex sponge.
+/// Every message is preceded by its kind and its label, and byte strings and scalar
+/// sequences by their length, so that distinct sequences of messages absorb distinct
+/// sequences of field elements.
+#[derive(Debug, Clone)]
+pub struct PoseidonTranscript {
+    sponge: PoseidonSponge,
+}
+
+/// Kind of a transcript message, absorbed before its label.
+#[derive(Debug, Clone, Copy)]
+enum MessageKind {
+    Protocol = 0,
+    Scalar = 1,
+    Scalars = 2,
+    Bytes = 3,
+    Challenge = 4,
+}
+
+impl PoseidonTranscript {
+    pub fn new(label: &[u8]) -> PoseidonTranscript {
+        let mut transcript = PoseidonTranscript {
+            sponge: PoseidonSponge::new(TRANSCRIPT_RATE, Fr::ZERO).unwrap(),
+        };
+        transcript.append_label(MessageKind::Protocol, label);
+        transcript
+    }
+
+    pub fn append_scalar(&mut self, label: &[u8], scalar: &Fr) {
+        self.append_label(MessageKind::Scalar, label);
+        self.sponge.absorb(scalar);
+    }
+
+    pub fn append_scalars(&mut self, label: &[u8], scalars: &[Fr]) {
+        self.append_label(MessageKind::Scalars, label);
+        self.sponge.absorb(&Fr::from(scalars.len() as u64));
+        self.sponge.absorb_slice(scalars);
+    }
+
+    /// Appends `bytes` packed 31 bytes per field element.
+    pub fn append_bytes(&mut self, label: &[u8], bytes: &[u8]) {
+        self.append_label(MessageKind::Bytes, label);
+        self.absorb_bytes(bytes);
+    }
+
+    pub fn challenge_scalar(&mut self, label: &[u8]) -> Fr {
+        self.append_label(MessageKind::Challenge, label);
+        self.sponge.squeeze()
+    }
+
+    fn append_label(&mut self, kind: MessageKind, label: &[u8]) {
+        self.sponge.absorb(&Fr::from(kind as u64));
+        self.absorb_bytes(label);
+    }
+
+    fn absorb_bytes(&mut self, bytes: &[u8]) {
+        self.sponge.absorb(&Fr::from(bytes.len() as u64));
+        self.sponge.absorb_slice(&bytes_to_fields(bytes));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poseidon_fields;
+
+    #[test]
+    fn test_sponge() {
+        PoseidonSponge::new(0, Fr::ZERO).expect_err("Rate 0");
+        PoseidonSponge::new(17, Fr::ZERO).expect_err("Rate 17");
+        assert_eq!(domain_tag(&[1, 2]).unwrap(), Fr::from(0x0201));
+        assert_eq!(
+            domain_tag(&[0xff; 31]).unwrap(),
+            bytes_to_fields(&[0xff; 31])[0]
+        );
+        assert!(matches!(
+            domain_tag(&[0; 32]),
+            Err(PoseidonError::DomainTagTooLong(32))
+        ));
+
+        // Absorbing a single block and squeezing once is one permutation of `[iv, inputs...]`.
+        let mut sponge = PoseidonSponge::new(2, Fr::ZERO).unwrap();
+        sponge.absorb_slice(&[Fr::from(1), Fr::from(2)]);
+        let mut state = vec![Fr::ZERO, Fr::from(1), Fr::from(2)];
+        poseidon_default().permute(&mut state).unwrap();
+        assert_eq!(sponge.squeeze(), state[1]);
+        assert_eq!(sponge.squeeze(), state[2]);
+        assert_eq!(
+            poseidon_fields(&[Fr::from(1), Fr::from(2)]).unwrap(),
+            state[0]
+        );
+    }
+
+    #[test]
+    fn test_transcript() {
+        let run = |label: &[u8], bytes: &[u8]| {
+            let mut transcript = PoseidonTranscript::new(b"test");
+            transcript.append_scalar(b"a", &Fr::from(5));
+            transcript.append_bytes(label, bytes);
+            let c0 = transcript.challenge_scalar(b"c");
+            let c1 = transcript.challenge_scalar(b"c");
+            assert_ne!(c0, c1);
+            c0
+        };
+        let c = run(b"b", b"hello");
+        assert_eq!(c, run(b"b", b"hello"));
+        assert_ne!(c, run(b"b", b"hello\0"));
+        assert_ne!(c, run(b"b\x05", b"hello"));
+        assert_ne!(c, run(b"B", b"hello"));
+    }
+
+    #[test]
+    fn test_transcript_message_kinds() {
+        let challenge = |append: &dyn Fn(&mut PoseidonTranscript)| {
+            let mut transcript = PoseidonTranscript::new(b"test");
+            append(&mut transcript);
+            transcript.challenge_scalar(b"c")
+        };
+        // Without a kind tag, all three absorb `[label, 0]`.
+        let scalar = challenge(&|t| t.append_scalar(b"l", &Fr::ZERO));
+        let scalars = challenge(&|t| t.append_scalars(b"l", &[]));
+        let bytes = challenge(&|t| t.append_bytes(b"l", b""));
+        assert_ne!(scalar, scalars);
+        assert_ne!(scalar, bytes);
+        assert_ne!(scalars, bytes);
+    }
+}
@@ -0,0 +1,567 @@
+use crate::Fr;
+use halo2curves::ff::*;
+use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Key of a stored node, `(level, index)`.
+pub type NodeKey = (usize, u64);
+
+/// Level reserved for metadata. Tree nodes only use levels `0..=MAX_DEPTH`,
+/// and levels just below this one are reserved for per-leaf data of the tree types built on top.
+pub const METADATA_LEVEL: usize = usize::MAX;
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Corrupted store: {0}")]
+    Corrupted(String),
+}
+
+/// Backend holding the nodes of a Poseidon Merkle tree.
+pub trait NodeStore {
+    fn get(&self, key: NodeKey) -> Result<Option<Fr>, StorageError>;
+    fn put(&mut self, key: NodeKey, value: Fr) -> Result<(), StorageError>;
+    /// Whether the store holds no node, including metadata and pending writes.
+    fn is_empty(&self) -> bool;
+    /// Makes all previous writes durable.
+    fn flush(&mut self) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    nodes: HashMap<NodeKey, Fr>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl NodeStore for MemoryStore {
+    fn get(&self, key: NodeKey) -> Result<Option<Fr>, StorageError> {
+        Ok(self.nodes.get(&key).copied())
+    }
+
+    fn put(&mut self, key: NodeKey, value: Fr) -> Result<(), StorageError> {
+        self.nodes.insert(key, value);
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        MemoryStore::is_empty(self)
+    }
+}
+
+/// Size of a log record: level (u64 LE), index (u64 LE) and the value representation.
+const RECORD_SIZE: usize = 8 + 8 + 32;
+/// Size of the record count (u64 LE) heading a batch.
+const HEADER_SIZE: usize = 8;
+/// Size of the SHA3-256 checksum closing a batch.
+const CHECKSUM_SIZE: usize = 32;
+
+/// File-backed store: an append-only log of `(key, value)` records with an in-memory index
+/// of the latest record per key, rebuilt by streaming the log on open.
+///
+/// Records are written in batches, one per [`NodeStore::flush`]: a record count, the records
+/// and a SHA3-256 checksum of both, which commits the batch. On open, a trailing batch that is
+/// incomplete or fails its checksum is the result of an interrupted flush and is discarded, so
+/// a crash loses the last flush as a whole. Writes are buffered in memory until then, and
+/// dropping the store without flushing discards them.
+///
+/// Overwritten records stay in the log until [`FileStore::compact`] rewrites it.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    file: File,
+    index: HashMap<NodeKey, u64>,
+    pending: HashMap<NodeKey, Fr>,
+    buffer: Vec<u8>,
+    len: u64,
+    /// Number of bytes the next log write stops after, failing, to simulate a short write.
+    #[cfg(test)]
+    short_write: Option<usize>,
+}
+
+impl FileStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileStore, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(&file);
+        let mut index = HashMap::new();
+        let mut len = 0;
+        while let Some(batch) = read_batch(&mut reader, len, file_len)? {
+            index.extend(batch.records);
+            len = batch.end;
+        }
+        drop(reader);
+        if len != file_len {
+            file.set_len(len)?;
+        }
+        Ok(FileStore {
+            path,
+            file,
+            index,
+            pending: HashMap::new(),
+            buffer: Vec::new(),
+            len,
+            #[cfg(test)]
+            short_write: None,
+        })
+    }
+
+    /// Number of distinct keys in the store.
+    pub fn len(&self) -> usize {
+        self.index.len()
+            + self
+                .pending
+                .keys()
+                .filter(|key| !self.index.contains_key(key))
+                .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Size of the log in bytes, excluding unflushed writes.
+    pub fn log_len(&self) -> u64 {
+        self.len
+    }
+
+    /// Flushes, then rewrites the log as a single batch holding only the latest record of
+    /// each key. The new log is written next to the old one and atomically renamed over it.
+    pub fn compact(&mut self) -> Result<(), StorageError> {
+        self.flush()?;
+        let mut compact_path = self.path.clone().into_os_string();
+        compact_path.push(".compact");
+        let compact_path = PathBuf::from(compact_path);
+
+        let mut writer = BatchWriter::new(BufWriter::new(File::create(&compact_path)?));
+        writer.write(&(self.index.len() as u64).to_le_bytes())?;
+        let mut index = HashMap::with_capacity(self.index.len());
+        let mut offset = HEADER_SIZE as u64;
+        for (key, record_offset) in self.index.iter() {
+            let mut record = [0u8; RECORD_SIZE];
+            read_exact_at(&self.file, &mut record, *record_offset)?;
+            writer.write(&record)?;
+            index.insert(*key, offset);
+            offset += RECORD_SIZE as u64;
+        }
+        let file = writer.finish()?.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&compact_path, &self.path)?;
+
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        self.index = index;
+        self.len = offset + CHECKSUM_SIZE as u64;
+        Ok(())
+    }
+}
+
+impl NodeStore for FileStore {
+    fn get(&self, key: NodeKey) -> Result<Option<Fr>, StorageError> {
+        if let Some(value) = self.pending.get(&key) {
+            return Ok(Some(*value));
+        }
+        let offset = match self.index.get(&key) {
+            Some(offset) => *offset,
+            None => return Ok(None),
+        };
+        let mut record = [0u8; RECORD_SIZE];
+        read_exact_at(&self.file, &mut record, offset)?;
+        match decode_record(&record)? {
+            (stored_key, Some(value)) if stored_key == key => Ok(Some(value)),
+            _ => Err(StorageError::Corrupted(format!(
+                "unexpected record at offset {}",
+                offset
+            ))),
+        }
+    }
+
+    fn put(&mut self, key: NodeKey, value: Fr) -> Result<(), StorageError> {
+        self.buffer.extend_from_slice(&(key.0 as u64).to_le_bytes());
+        self.buffer.extend_from_slice(&key.1.to_le_bytes());
+        self.buffer.extend_from_slice(value.to_repr().as_ref());
+        self.pending.insert(key, value);
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        FileStore::is_empty(self)
+    }
+
+    fn flush(&mut self) -> Result<(), StorageError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let count = (self.buffer.len() / RECORD_SIZE) as u64;
+        let mut writer = BatchWriter::new(Vec::with_capacity(
+            HEADER_SIZE + self.buffer.len() + CHECKSUM_SIZE,
+        ));
+        writer.write(&count.to_le_bytes())?;
+        writer.write(&self.buffer)?;
+        let batch = writer.finish()?;
+        if let Err(e) = self.write_log(&batch).and_then(|_| self.file.sync_data()) {
+            // Drop a partially written batch, so that the next flush appends right after
+            // the last committed one. The writes stay buffered for that flush.
+            self.file.set_len(self.len)?;
+            return Err(e.into());
+        }
+
+        let records_start = self.len + HEADER_SIZE as u64;
+        for (i, record) in self.buffer.chunks_exact(RECORD_SIZE).enumerate() {
+            let (key, _) = decode_record(record)?;
+            self.index
+                .insert(key, records_start + (i * RECORD_SIZE) as u64);
+        }
+        self.len += batch.len() as u64;
+        self.buffer.clear();
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl FileStore {
+    #[cfg(not(test))]
+    fn write_log(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(bytes)
+    }
+
+    #[cfg(test)]
+    fn write_log(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        match self.short_write.take() {
+            Some(limit) => {
+                self.file.write_all(&bytes[..limit.min(bytes.len())])?;
+                Err(std::io::ErrorKind::WriteZero.into())
+            }
+            None => self.file.write_all(bytes),
+        }
+    }
+}
+
+/// Writer accumulating the checksum of a batch, appended by `finish`.
+struct BatchWriter<W: Write> {
+    inner: W,
+    hasher: Sha3_256,
+}
+
+impl<W: Write> BatchWriter<W> {
+    fn new(inner: W) -> BatchWriter<W> {
+        BatchWriter {
+            inner,
+            hasher: Sha3_256::new(),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), StorageError> {
+        self.hasher.update(bytes);
+        Ok(self.inner.write_all(bytes)?)
+    }
+
+    fn finish(mut self) -> Result<W, StorageError> {
+        self.inner.write_all(&self.hasher.finalize())?;
+        Ok(self.inner)
+    }
+}
+
+/// Committed batch read from the log: its records as `(key, offset)` and its end offset.
+struct Batch {
+    records: Vec<(NodeKey, u64)>,
+    end: u64,
+}
+
+/// Reads the batch starting at `start`, `None` at the end of the log or if the batch is an
+/// uncommitted tail. A complete batch with a wrong checksum followed by more data is corruption.
+fn read_batch<R: Read>(
+    reader: &mut R,
+    start: u64,
+    file_len: u64,
+) -> Result<Option<Batch>, StorageError> {
+    let mut header = [0u8; HEADER_SIZE];
+    if !read_full(reader, &mut header)? {
+        return Ok(None);
+    }
+    let count = u64::from_le_bytes(header);
+    let end = count
+        .checked_mul(RECORD_SIZE as u64)
+        .and_then(|size| size.checked_add(start + (HEADER_SIZE + CHECKSUM_SIZE) as u64));
+    let end = match end {
+        Some(end) if end <= file_len => end,
+        _ => return Ok(None),
+    };
+
+    let mut hasher = Sha3_256::new();
+    hasher.update(header);
+    let mut records = Vec::with_capacity(count as usize);
+    let mut record = [0u8; RECORD_SIZE];
+    let mut offset = start + HEADER_SIZE as u64;
+    let mut non_canonical = None;
+    for _ in 0..count {
+        reader.read_exact(&mut record)?;
+        hasher.update(record);
+        match decode_record(&record)? {
+            (key, Some(_)) => records.push((key, offset)),
+            _ => non_canonical = non_canonical.or(Some(offset)),
+        }
+        offset += RECORD_SIZE as u64;
+    }
+    let mut checksum = [0u8; CHECKSUM_SIZE];
+    reader.read_exact(&mut checksum)?;
+    if hasher.finalize().as_slice() != checksum {
+        return check_tail(end, file_len, start);
+    }
+    if let Some(offset) = non_canonical {
+        return Err(StorageError::Corrupted(format!(
+            "non-canonical value at offset {}",
+            offset
+        )));
+    }
+    Ok(Some(Batch { records, end }))
+}
+
+/// Invalid batches are only expected as the last one of the log.
+fn check_tail(end: u64, file_len: u64, start: u64) -> Result<Option<Batch>, StorageError> {
+    if end == file_len {
+        Ok(None)
+    } else {
+        Err(StorageError::Corrupted(format!(
+            "invalid batch at offset {}",
+            start
+        )))
+    }
+}
+
+/// Fills `buf`, `false` if the reader is already at its end.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool, StorageError> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 if read == 0 => return Ok(false),
+            0 => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+            n => read += n,
+        }
+    }
+    Ok(true)
+}
+
+/// Positional read that does not move the shared file cursor, so concurrent `get`s don't race.
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(_file: &File, _buf: &mut [u8], _offset: u64) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+fn decode_record(record: &[u8]) -> Result<(NodeKey, Option<Fr>), StorageError> {
+    let level = u64::from_le_bytes(record[0..8].try_into().unwrap());
+    let index = u64::from_le_bytes(record[8..16].try_into().unwrap());
+    let level = usize::try_from(level)
+        .map_err(|_| StorageError::Corrupted(format!("invalid level {}", level)))?;
+    let mut repr = <Fr as PrimeField>::Repr::default();
+    repr.as_mut().copy_from_slice(&record[16..RECORD_SIZE]);
+    Ok(((level, index), Fr::from_repr(repr).into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("poseidon-rs-{}-{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let mut store = MemoryStore::new();
+        assert_eq!(store.get((0, 1)).unwrap(), None);
+        store.put((0, 1), Fr::from(7)).unwrap();
+        store.put((0, 1), Fr::from(8)).unwrap();
+        assert_eq!(store.get((0, 1)).unwrap(), Some(Fr::from(8)));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_file_store_reopen() {
+        let path = temp_path("reopen");
+        {
+            let mut store = FileStore::open(&path).unwrap();
+            store.put((0, 1), Fr::from(7)).unwrap();
+            store.put((METADATA_LEVEL, 0), Fr::from(3)).unwrap();
+            assert_eq!(store.get((0, 1)).unwrap(), Some(Fr::from(7)));
+            store.flush().unwrap();
+            store.put((0, 1), -Fr::ONE).unwrap();
+            store.flush().unwrap();
+            // Dropped without a flush.
+            store.put((0, 2), Fr::from(9)).unwrap();
+        }
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get((0, 1)).unwrap(), Some(-Fr::ONE));
+        assert_eq!(store.get((METADATA_LEVEL, 0)).unwrap(), Some(Fr::from(3)));
+        assert_eq!(store.get((0, 2)).unwrap(), None);
+        assert_eq!(
+            store.log_len(),
+            (2 * (HEADER_SIZE + CHECKSUM_SIZE) + 3 * RECORD_SIZE) as u64
+        );
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        assert_eq!(store.get((0, 1)).unwrap(), Some(-Fr::ONE));
+                        assert_eq!(store.get((METADATA_LEVEL, 0)).unwrap(), Some(Fr::from(3)));
+                    }
+                });
+            }
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_store_torn_writes() {
+        let path = temp_path("torn");
+        let mut store = FileStore::open(&path).unwrap();
+        store.put((0, 0), Fr::from(1)).unwrap();
+        store.flush().unwrap();
+        let committed = store.log_len();
+        store.put((0, 0), Fr::from(2)).unwrap();
+        store.put((0, 1), Fr::from(3)).unwrap();
+        store.flush().unwrap();
+        drop(store);
+        let log = std::fs::read(&path).unwrap();
+
+        // Cut on a record boundary before the checksum, inside the checksum, or with a
+        // corrupted checksum: the second batch is discarded as a whole.
+        let record_boundary = committed as usize + HEADER_SIZE + RECORD_SIZE;
+        let mut corrupted = log.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        for torn in [
+            log[..record_boundary].to_vec(),
+            log[..log.len() - 1].to_vec(),
+            corrupted,
+        ] {
+            std::fs::write(&path, &torn).unwrap();
+            let store = FileStore::open(&path).unwrap();
+            assert_eq!(store.get((0, 0)).unwrap(), Some(Fr::from(1)));
+            assert_eq!(store.get((0, 1)).unwrap(), None);
+            assert_eq!(std::fs::metadata(&path).unwrap().len(), committed);
+        }
+
+        // A bad batch that is not the last one is reported.
+        let mut corrupted = log.clone();
+        corrupted[HEADER_SIZE] ^= 1;
+        std::fs::write(&path, &corrupted).unwrap();
+        assert!(matches!(
+            FileStore::open(&path),
+            Err(StorageError::Corrupted(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_store_short_write() {
+        let path = temp_path("short");
+        let mut store = FileStore::open(&path).unwrap();
+        store.put((0, 0), Fr::from(1)).unwrap();
+        store.flush().unwrap();
+        let committed = store.log_len();
+
+        store.put((0, 1), Fr::from(2)).unwrap();
+        store.short_write = Some(HEADER_SIZE + 5);
+        assert!(matches!(store.flush(), Err(StorageError::Io(_))));
+        assert_eq!(store.log_len(), committed);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), committed);
+        assert_eq!(store.get((0, 1)).unwrap(), Some(Fr::from(2)));
+
+        // The retried flush and later ones land right after the committed batch.
+        store.flush().unwrap();
+        store.put((0, 2), Fr::from(3)).unwrap();
+        store.flush().unwrap();
+        assert_eq!(store.log_len(), std::fs::metadata(&path).unwrap().len());
+        drop(store);
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 3);
+        for i in 0..3 {
+            assert_eq!(store.get((0, i)).unwrap(), Some(Fr::from(i + 1)));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_store_compact() {
+        let path = temp_path("compact");
+        let mut store = FileStore::open(&path).unwrap();
+        for round in 0..10u64 {
+            for i in 0..5 {
+                store.put((0, i), Fr::from(round * i)).unwrap();
+            }
+            store.flush().unwrap();
+        }
+        let before = store.log_len();
+        store.put((1, 0), Fr::from(42)).unwrap();
+        store.compact().unwrap();
+        assert!(store.log_len() < before);
+        assert_eq!(store.log_len(), std::fs::metadata(&path).unwrap().len());
+        store.put((1, 1), Fr::from(43)).unwrap();
+        store.flush().unwrap();
+        drop(store);
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 7);
+        for i in 0..5 {
+            assert_eq!(store.get((0, i)).unwrap(), Some(Fr::from(9 * i)));
+        }
+        assert_eq!(store.get((1, 0)).unwrap(), Some(Fr::from(42)));
+        assert_eq!(store.get((1, 1)).unwrap(), Some(Fr::from(43)));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
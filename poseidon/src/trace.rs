@@ -0,0 +1,99 @@
+use crate::utils::fr_to_decimal;
+use crate::Fr;
+use serde::{Serialize, Serializer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundType {
+    Full,
+    Partial,
+}
+
+impl RoundType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoundType::Full => "full",
+            RoundType::Partial => "partial",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceStep {
+    Ark,
+    Sbox,
+    Mix,
+}
+
+impl TraceStep {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TraceStep::Ark => "ark",
+            TraceStep::Sbox => "sbox",
+            TraceStep::Mix => "mix",
+        }
+    }
+}
+
+/// State right after a step of a round.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceRow {
+    pub round: usize,
+    pub round_type: RoundType,
+    pub step: TraceStep,
+    #[serde(serialize_with = "decimal_fields")]
+    pub state: Vec<Fr>,
+}
+
+/// Every intermediate state of a hash, as returned by `Poseidon::hash_with_trace`.
+/// Field elements serialize as decimal strings, the format of circom witness tooling.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Trace {
+    #[serde(serialize_with = "decimal_fields")]
+    pub inputs: Vec<Fr>,
+    #[serde(serialize_with = "decimal_fields")]
+    pub initial_state: Vec<Fr>,
+    pub rows: Vec<TraceRow>,
+    #[serde(serialize_with = "decimal_field")]
+    pub output: Fr,
+}
+
+impl Trace {
+    /// JSON serialization of the trace.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Trace serializes to JSON")
+    }
+
+    /// CSV with a header row and one row per step: `round,round_type,step,state_0,...`,
+    /// field elements in decimal.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("round,round_type,step");
+        for i in 0..self.initial_state.len() {
+            csv.push_str(&format!(",state_{}", i));
+        }
+        csv.push('\n');
+        for row in self.rows.iter() {
+            csv.push_str(&format!(
+                "{},{},{}",
+                row.round,
+                row.round_type.as_str(),
+                row.step.as_str()
+            ));
+            for field in row.state.iter() {
+                csv.push(',');
+                csv.push_str(&fr_to_decimal(field));
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+fn decimal_field<S: Serializer>(field: &Fr, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&fr_to_decimal(field))
+}
+
+fn decimal_fields<S: Serializer>(fields: &[Fr], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(fields.iter().map(fr_to_decimal))
+}
@@ -0,0 +1,88 @@
+use crate::Fr;
+use halo2curves::ff::*;
+use num_bigint::BigUint;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Big-endian bytes of the canonical representation, so that byte order matches integer order.
+pub(crate) fn fr_to_be_bytes(field: &Fr) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(field.to_repr().as_ref());
+    bytes.reverse();
+    bytes
+}
+
+/// `0x`-prefixed big-endian hex, the same format as the `Debug` output of `Fr`.
+pub(crate) fn fr_to_hex(field: &Fr) -> String {
+    let mut hex = String::from("0x");
+    for byte in fr_to_be_bytes(field).iter() {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    hex
+}
+
+/// Decimal representation of the canonical value, as used by circom witness files.
+pub(crate) fn fr_to_decimal(field: &Fr) -> String {
+    BigUint::from_bytes_le(field.to_repr().as_ref()).to_string()
+}
+
+/// Inverse of `Fr::from(u64)`, `None` if the value does not fit in a `u64`.
+pub(crate) fn fr_to_u64(field: &Fr) -> Option<u64> {
+    let repr = field.to_repr();
+    let bytes = repr.as_ref();
+    if bytes[8..].iter().any(|b| *b != 0) {
+        return None;
+    }
+    let mut low = [0u8; 8];
+    low.copy_from_slice(&bytes[..8]);
+    Some(u64::from_le_bytes(low))
+}
+
+/// Maps `f` over `items`, across threads with the `parallel` feature. Output order follows `items`.
+#[cfg(feature = "parallel")]
+pub(crate) fn map<T, U, F>(items: &[T], f: F) -> Vec<U>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(&T) -> U + Sync + Send,
+{
+    items.par_iter().map(f).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn map<T, U, F>(items: &[T], f: F) -> Vec<U>
+where
+    F: Fn(&T) -> U,
+{
+    items.iter().map(f).collect()
+}
+
+/// Packs `bytes` into field elements of 31 bytes each, little-endian within each element.
+pub(crate) fn bytes_to_fields(bytes: &[u8]) -> Vec<Fr> {
+    bytes
+        .chunks(31)
+        .map(|chunk| {
+            let mut repr = <Fr as PrimeField>::Repr::default();
+            repr.as_mut()[..chunk.len()].copy_from_slice(chunk);
+            Fr::from_repr(repr).unwrap()
+        })
+        .collect()
+}
+
+/// Inverse of [`fr_to_hex`], the `0x` prefix is optional. `None` for non-canonical values.
+pub(crate) fn fr_from_hex(hex: &str) -> Option<Fr> {
+    let hex = hex.strip_prefix("0x").unwrap_or(hex);
+    if hex.is_empty() || hex.len() > 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut repr = <Fr as PrimeField>::Repr::default();
+    for (i, byte) in repr.as_mut().iter_mut().enumerate() {
+        let end = match hex.len().checked_sub(2 * i) {
+            Some(end) if end > 0 => end,
+            _ => break,
+        };
+        let start = end.saturating_sub(2);
+        *byte = u8::from_str_radix(&hex[start..end], 16).ok()?;
+    }
+    Fr::from_repr(repr).into()
+}
@@ -0,0 +1,248 @@
+//! Derivations of zk-email's relayer-utils, matching its circuits.
+
+use crate::utils::bytes_to_fields;
+use crate::{poseidon_fields, Fr, PoseidonError};
+use halo2curves::ff::*;
+use rand_core::RngCore;
+use thiserror::Error;
+
+/// Length email addresses are zero-padded to, `MAX_EMAIL_ADDR_BYTES` in relayer-utils.
+pub const MAX_EMAIL_ADDR_BYTES: usize = 256;
+
+#[derive(Error, Debug)]
+pub enum ZkEmailError {
+    #[error("Email address too long: max length is `{0}` bytes but got `{1}`")]
+    EmailAddrTooLong(usize, usize),
+    #[error("Input too long: max length is `{0}` bytes but got `{1}`")]
+    InputTooLong(usize, usize),
+    #[error("Wrong mask length: expected `{0}` but got `{1}`")]
+    WrongMaskLength(usize, usize),
+    #[error(transparent)]
+    Poseidon(#[from] PoseidonError),
+}
+
+/// Packs `bytes` into field elements of 31 bytes each, little-endian within each element.
+pub fn bytes2fields(bytes: &[u8]) -> Vec<Fr> {
+    bytes_to_fields(bytes)
+}
+
+/// `PoseidonModular` of the zk-email circuits: inputs are hashed in chunks of 16
+/// (the last one possibly shorter), and chunk hashes are chained with `Poseidon(acc, chunk)`.
+pub fn poseidon_modular(fields: &[Fr]) -> Result<Fr, PoseidonError> {
+    let mut chunks = fields.chunks(16);
+    let first = chunks.next().unwrap_or(&[]);
+    let mut out = poseidon_fields(first)?;
+    for chunk in chunks {
+        out = poseidon_fields(&[out, poseidon_fields(chunk)?])?;
+    }
+    Ok(out)
+}
+
+/// Revealed part of a header or body as exposed by the circuits' masking outputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaskedReveal {
+    /// Input bytes multiplied by the mask and zero-padded to the circuit's maximum length.
+    pub masked_bytes: Vec<u8>,
+    /// `masked_bytes` packed 31 bytes per element, as `PackBytes` does.
+    pub packed: Vec<Fr>,
+    /// [`poseidon_modular`] of `packed`.
+    pub commitment: Fr,
+}
+
+impl MaskedReveal {
+    /// Masks `bytes` with one flag per byte and pads the result to `max_bytes`,
+    /// the maximum length the circuit was compiled for.
+    pub fn new(
+        bytes: &[u8],
+        mask: &[bool],
+        max_bytes: usize,
+    ) -> Result<MaskedReveal, ZkEmailError> {
+        if bytes.len() > max_bytes {
+            return Err(ZkEmailError::InputTooLong(max_bytes, bytes.len()));
+        }
+        if mask.len() != bytes.len() {
+            return Err(ZkEmailError::WrongMaskLength(bytes.len(), mask.len()));
+        }
+        let mut masked_bytes: Vec<u8> = bytes
+            .iter()
+            .zip(mask.iter())
+            .map(|(byte, reveal)| if *reveal { *byte } else { 0 })
+            .collect();
+        masked_bytes.resize(max_bytes, 0);
+        let packed = bytes2fields(&masked_bytes);
+        let commitment = poseidon_modular(&packed)?;
+        Ok(MaskedReveal {
+            masked_bytes,
+            packed,
+            commitment,
+        })
+    }
+
+    /// Reveals the bytes in `range` only.
+    pub fn from_range(
+        bytes: &[u8],
+        range: std::ops::Range<usize>,
+        max_bytes: usize,
+    ) -> Result<MaskedReveal, ZkEmailError> {
+        let mask: Vec<bool> = (0..bytes.len()).map(|i| range.contains(&i)).collect();
+        MaskedReveal::new(bytes, &mask, max_bytes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaddedEmailAddr {
+    pub email_addr: String,
+    pub padded_bytes: Vec<u8>,
+}
+
+impl PaddedEmailAddr {
+    pub fn from_email_addr(email_addr: &str) -> Result<PaddedEmailAddr, ZkEmailError> {
+        let len = email_addr.len();
+        if len > MAX_EMAIL_ADDR_BYTES {
+            return Err(ZkEmailError::EmailAddrTooLong(MAX_EMAIL_ADDR_BYTES, len));
+        }
+        let mut padded_bytes = email_addr.as_bytes().to_vec();
+        padded_bytes.resize(MAX_EMAIL_ADDR_BYTES, 0);
+        Ok(PaddedEmailAddr {
+            email_addr: email_addr.to_string(),
+            padded_bytes,
+        })
+    }
+
+    /// The padded bytes packed with [`bytes2fields`], 9 elements.
+    pub fn to_email_addr_fields(&self) -> Vec<Fr> {
+        bytes2fields(&self.padded_bytes)
+    }
+
+    pub fn to_commitment(&self, rand: &Fr) -> Result<Fr, PoseidonError> {
+        EmailAddrCommit::new(self, rand).map(|commit| commit.0)
+    }
+}
+
+/// Random secret of an account, hashed with the email address into its [`AccountSalt`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountCode(pub Fr);
+
+impl AccountCode {
+    pub fn new<R: RngCore>(rng: R) -> AccountCode {
+        AccountCode(Fr::random(rng))
+    }
+}
+
+impl From<Fr> for AccountCode {
+    fn from(code: Fr) -> AccountCode {
+        AccountCode(code)
+    }
+}
+
+/// `Poseidon(email_addr_fields, account_code, 0)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountSalt(pub Fr);
+
+impl AccountSalt {
+    pub fn new(
+        email_addr: &PaddedEmailAddr,
+        account_code: &AccountCode,
+    ) -> Result<AccountSalt, PoseidonError> {
+        let mut inputs = email_addr.to_email_addr_fields();
+        inputs.push(account_code.0);
+        inputs.push(Fr::ZERO);
+        Ok(AccountSalt(poseidon_fields(&inputs)?))
+    }
+}
+
+/// `Poseidon(rand, email_addr_fields)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmailAddrCommit(pub Fr);
+
+impl EmailAddrCommit {
+    pub fn new(email_addr: &PaddedEmailAddr, rand: &Fr) -> Result<EmailAddrCommit, PoseidonError> {
+        let mut inputs = vec![*rand];
+        inputs.extend(email_addr.to_email_addr_fields());
+        Ok(EmailAddrCommit(poseidon_fields(&inputs)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::PoseidonRng;
+
+    #[test]
+    fn test_bytes2fields() {
+        let mut bytes = vec![0u8; 33];
+        bytes[0] = 1;
+        bytes[30] = 2;
+        bytes[31] = 3;
+        bytes[32] = 4;
+        let fields = bytes2fields(&bytes);
+        assert_eq!(
+            fields,
+            vec![
+                Fr::from(1) + Fr::from(2) * Fr::from(256).pow_vartime([30]),
+                Fr::from(3 + 4 * 256)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_poseidon_modular() {
+        let fields: Vec<Fr> = (0..35).map(Fr::from).collect();
+        assert_eq!(
+            poseidon_modular(&fields[..16]).unwrap(),
+            poseidon_fields(&fields[..16]).unwrap()
+        );
+        let chunk_0 = poseidon_fields(&fields[..16]).unwrap();
+        let chunk_1 = poseidon_fields(&fields[16..32]).unwrap();
+        let chunk_2 = poseidon_fields(&fields[32..]).unwrap();
+        let expected = poseidon_fields(&[chunk_0, chunk_1]).unwrap();
+        let expected = poseidon_fields(&[expected, chunk_2]).unwrap();
+        assert_eq!(poseidon_modular(&fields).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_masked_reveal() {
+        let header = b"subject:Send 1 ETH to bob";
+        let reveal = MaskedReveal::from_range(header, 8..18, 64).unwrap();
+        assert_eq!(&reveal.masked_bytes[..18], b"\0\0\0\0\0\0\0\0Send 1 ETH");
+        assert!(reveal.masked_bytes[18..].iter().all(|b| *b == 0));
+        assert_eq!(reveal.masked_bytes.len(), 64);
+        assert_eq!(reveal.packed, bytes2fields(&reveal.masked_bytes));
+        assert_eq!(reveal.packed.len(), 3);
+        assert_eq!(reveal.commitment, poseidon_fields(&reveal.packed).unwrap());
+
+        let mask: Vec<bool> = (0..header.len()).map(|i| (8..18).contains(&i)).collect();
+        assert_eq!(MaskedReveal::new(header, &mask, 64).unwrap(), reveal);
+        MaskedReveal::new(header, &mask[1..], 64).expect_err("Wrong mask length");
+        MaskedReveal::from_range(header, 0..1, 16).expect_err("Too long");
+    }
+
+    #[test]
+    fn test_account_salt() {
+        let email_addr = PaddedEmailAddr::from_email_addr("alice@example.com").unwrap();
+        assert_eq!(email_addr.padded_bytes.len(), MAX_EMAIL_ADDR_BYTES);
+        let fields = email_addr.to_email_addr_fields();
+        assert_eq!(fields.len(), 9);
+        assert_eq!(fields[0], bytes2fields(b"alice@example.com")[0]);
+        assert!(fields[1..].iter().all(|field| *field == Fr::ZERO));
+
+        let account_code = AccountCode::new(PoseidonRng::from_fields(&[Fr::from(1)]));
+        let salt = AccountSalt::new(&email_addr, &account_code).unwrap();
+        let mut inputs = fields.clone();
+        inputs.extend_from_slice(&[account_code.0, Fr::ZERO]);
+        assert_eq!(salt.0, poseidon_fields(&inputs).unwrap());
+        assert_ne!(
+            salt,
+            AccountSalt::new(&email_addr, &AccountCode::from(Fr::from(1))).unwrap()
+        );
+
+        let commit = EmailAddrCommit::new(&email_addr, &Fr::from(5)).unwrap();
+        let inputs = [vec![Fr::from(5)], fields].concat();
+        assert_eq!(commit.0, poseidon_fields(&inputs).unwrap());
+        assert_eq!(email_addr.to_commitment(&Fr::from(5)).unwrap(), commit.0);
+
+        PaddedEmailAddr::from_email_addr(&"a".repeat(MAX_EMAIL_ADDR_BYTES)).unwrap();
+        PaddedEmailAddr::from_email_addr(&"a".repeat(MAX_EMAIL_ADDR_BYTES + 1))
+            .expect_err("Too long");
+    }
+}